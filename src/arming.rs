@@ -0,0 +1,204 @@
+use crate::attitude::Euler;
+use crate::errors::{I2cErrorExt, Result};
+use crate::mpu6050::Mpu6050;
+use embedded_hal_async::i2c::I2c;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum FlightMode {
+    Disarmed,
+    /// Sensor calibration in progress, the craft must stay still.
+    Calibrating,
+    Armed,
+    /// The link or a sensor failed while armed.
+    Failsafe,
+    /// Controlled descent before disarming.
+    Landing
+}
+
+impl FlightMode {
+    /// Whether the motors may spin in this mode.
+    pub fn motors_enabled(self) -> bool {
+        matches!(self, FlightMode::Armed | FlightMode::Failsafe | FlightMode::Landing)
+    }
+}
+
+/// Why an arm request was refused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum ArmRefusal {
+    /// Already armed, or in a mode that cannot arm.
+    NotDisarmed(FlightMode),
+    /// `Mpu6050::verify` has not passed or the chip config differs from the driver.
+    ImuNotVerified,
+    /// The IMU self-test has not passed.
+    SelfTestFailed,
+    NotLevel,
+    ThrottleHigh,
+    RcUnhealthy,
+    BatteryLow
+}
+
+/// Outcome of the IMU preflight checks.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub struct ImuCheck {
+    /// `Mpu6050::verify` passed and the chip config matches the driver.
+    pub verified: bool,
+    pub self_test_passed: bool
+}
+
+/// Runs the IMU checks that gate arming. Bus errors are returned instead of a failed check.
+pub async fn check_imu<I: I2c>(imu: &mut Mpu6050<I>) -> Result<ImuCheck>
+where
+    I::Error: I2cErrorExt
+{
+    imu.verify().await?;
+    Ok(ImuCheck {
+        verified: imu.verify_config().await?.is_empty(),
+        self_test_passed: imu.self_test().await?.passed()
+    })
+}
+
+/// Live inputs checked at the moment of arming.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ArmInputs {
+    pub attitude: Euler,
+    /// Throttle stick in `[0, 1]`.
+    pub throttle: f32,
+    pub rc_healthy: bool,
+    pub battery_volts: f32
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ArmingConfig {
+    /// Largest roll or pitch in radians accepted as level.
+    pub max_tilt: f32,
+    pub max_throttle: f32,
+    pub min_battery_volts: f32
+}
+
+impl Default for ArmingConfig {
+    fn default() -> Self {
+        ArmingConfig {
+            max_tilt: 25f32.to_radians(),
+            max_throttle: 0.05,
+            min_battery_volts: 10.5
+        }
+    }
+}
+
+/// Flight mode state machine that gates arming behind preflight checks.
+#[derive(Debug, Copy, Clone)]
+pub struct Arming {
+    pub config: ArmingConfig,
+    mode: FlightMode,
+    imu: ImuCheck
+}
+
+impl Arming {
+    pub fn new(config: ArmingConfig) -> Self {
+        Arming {
+            config,
+            mode: FlightMode::Disarmed,
+            imu: ImuCheck::default()
+        }
+    }
+
+    pub fn mode(&self) -> FlightMode {
+        self.mode
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.mode.motors_enabled()
+    }
+
+    fn transition(&mut self, mode: FlightMode) {
+        if mode != self.mode {
+            defmt::info!("flight mode {} -> {}", self.mode, mode);
+            self.mode = mode;
+        }
+    }
+
+    /// Records IMU checks run elsewhere, e.g. by the task that owns the IMU.
+    pub fn set_imu_check(&mut self, check: ImuCheck) {
+        self.imu = check;
+    }
+
+    /// Every reason arming would be refused right now.
+    pub fn refusals(&self, inputs: &ArmInputs) -> impl Iterator<Item = ArmRefusal> {
+        let config = self.config;
+        let level = inputs.attitude.roll.abs() <= config.max_tilt && inputs.attitude.pitch.abs() <= config.max_tilt;
+        let throttle_low = inputs.throttle <= config.max_throttle;
+        let battery_ok = inputs.battery_volts >= config.min_battery_volts;
+        [
+            (self.mode != FlightMode::Disarmed).then_some(ArmRefusal::NotDisarmed(self.mode)),
+            (!self.imu.verified).then_some(ArmRefusal::ImuNotVerified),
+            (!self.imu.self_test_passed).then_some(ArmRefusal::SelfTestFailed),
+            (!level).then_some(ArmRefusal::NotLevel),
+            (!throttle_low).then_some(ArmRefusal::ThrottleHigh),
+            (!inputs.rc_healthy).then_some(ArmRefusal::RcUnhealthy),
+            (!battery_ok).then_some(ArmRefusal::BatteryLow)
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Arms if every preflight check passes, otherwise logs each refusal and returns the first.
+    pub fn arm(&mut self, inputs: &ArmInputs) -> core::result::Result<(), ArmRefusal> {
+        let mut first = None;
+        for refusal in self.refusals(inputs) {
+            defmt::warn!("arming refused: {}", refusal);
+            first.get_or_insert(refusal);
+        }
+        match first {
+            Some(refusal) => Err(refusal),
+            None => {
+                self.transition(FlightMode::Armed);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn disarm(&mut self) {
+        self.transition(FlightMode::Disarmed);
+    }
+
+    /// Enters calibration; only allowed while disarmed.
+    pub fn start_calibration(&mut self) -> bool {
+        if self.mode != FlightMode::Disarmed {
+            return false
+        }
+        self.transition(FlightMode::Calibrating);
+        true
+    }
+
+    pub fn finish_calibration(&mut self) {
+        if self.mode == FlightMode::Calibrating {
+            self.transition(FlightMode::Disarmed);
+        }
+    }
+
+    /// Hands control to the failsafe; ignored unless the motors are running.
+    pub fn enter_failsafe(&mut self) {
+        if self.mode.motors_enabled() {
+            self.transition(FlightMode::Failsafe);
+        }
+    }
+
+    /// Returns from failsafe to normal flight once the fault has cleared.
+    pub fn recover(&mut self) {
+        if self.mode == FlightMode::Failsafe {
+            self.transition(FlightMode::Armed);
+        }
+    }
+
+    pub fn land(&mut self) {
+        if self.mode.motors_enabled() {
+            self.transition(FlightMode::Landing);
+        }
+    }
+}
+
+impl Default for Arming {
+    fn default() -> Self {
+        Arming::new(ArmingConfig::default())
+    }
+}
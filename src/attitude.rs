@@ -0,0 +1,365 @@
+use crate::mpu6050::ScaledSample;
+use core::ops::Mul;
+use libm::{asinf, atan2f, cosf, sinf, sqrtf, tanf};
+
+/// Estimator the flight loop runs: Mahony by default, the complementary filter when
+/// `attitude-complementary` is enabled or Mahony is not built.
+#[cfg(any(feature = "attitude-complementary", not(feature = "attitude-mahony")))]
+pub type Estimator = Complementary;
+#[cfg(all(feature = "attitude-mahony", not(feature = "attitude-complementary")))]
+pub type Estimator = Mahony;
+
+/// Rotation from the body frame to the earth frame, Z up.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 };
+
+    pub fn norm(&self) -> f32 {
+        sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    pub fn normalize(self) -> Self {
+        let norm = self.norm();
+        if norm == 0.0 {
+            return Quaternion::IDENTITY
+        }
+        Quaternion {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm
+        }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Quaternion { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    /// Advances the attitude by body rates `gyro` (rad/s) over `dt` seconds.
+    pub fn integrate(self, gyro: [f32; 3], dt: f32) -> Self {
+        let rate = Quaternion { w: 0.0, x: gyro[0], y: gyro[1], z: gyro[2] };
+        let dq = self * rate;
+        let half_dt = 0.5 * dt;
+        Quaternion {
+            w: self.w + dq.w * half_dt,
+            x: self.x + dq.x * half_dt,
+            y: self.y + dq.y * half_dt,
+            z: self.z + dq.z * half_dt
+        }.normalize()
+    }
+
+    /// Earth Z axis expressed in the body frame, i.e. the direction a resting accelerometer reads.
+    pub fn gravity(&self) -> [f32; 3] {
+        let Quaternion { w, x, y, z } = *self;
+        [
+            2.0 * (x * z - w * y),
+            2.0 * (w * x + y * z),
+            w * w - x * x - y * y + z * z
+        ]
+    }
+
+    /// Rotates a body-frame vector into the earth frame.
+    pub fn rotate(&self, v: [f32; 3]) -> [f32; 3] {
+        let p = Quaternion { w: 0.0, x: v[0], y: v[1], z: v[2] };
+        let r = *self * p * self.conjugate();
+        [r.x, r.y, r.z]
+    }
+
+    pub fn from_euler(euler: Euler) -> Self {
+        let (sr, cr) = (sinf(euler.roll * 0.5), cosf(euler.roll * 0.5));
+        let (sp, cp) = (sinf(euler.pitch * 0.5), cosf(euler.pitch * 0.5));
+        let (sy, cy) = (sinf(euler.yaw * 0.5), cosf(euler.yaw * 0.5));
+        Quaternion {
+            w: cr * cp * cy + sr * sp * sy,
+            x: sr * cp * cy - cr * sp * sy,
+            y: cr * sp * cy + sr * cp * sy,
+            z: cr * cp * sy - sr * sp * cy
+        }
+    }
+
+    pub fn to_euler(&self) -> Euler {
+        let Quaternion { w, x, y, z } = *self;
+        Euler {
+            roll: atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y)),
+            pitch: asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0)),
+            yaw: atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z))
+        }
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w
+        }
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::IDENTITY
+    }
+}
+
+/// Z-Y-X Tait-Bryan angles in radians.
+#[derive(Debug, Default, Copy, Clone, PartialEq, defmt::Format)]
+pub struct Euler {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32
+}
+
+pub trait AttitudeEstimator {
+    /// Feeds one calibrated sample taken `dt` seconds after the previous one.
+    fn update(&mut self, sample: &ScaledSample, dt: f32);
+
+    fn quaternion(&self) -> Quaternion;
+
+    fn euler(&self) -> Euler {
+        self.quaternion().to_euler()
+    }
+
+    fn reset(&mut self);
+}
+
+/// Unit vector of `v`, or `None` if it carries no direction.
+pub(crate) fn unit(v: [f32; 3]) -> Option<[f32; 3]> {
+    let norm = sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+    (norm > f32::EPSILON).then(|| v.map(|c| c / norm))
+}
+
+#[cfg(feature = "attitude-mahony")]
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+/// Blends gyro-integrated roll and pitch with the accelerometer tilt; yaw is gyro only.
+#[derive(Debug, Copy, Clone)]
+pub struct Complementary {
+    /// Seconds over which the accelerometer pulls roll and pitch back; larger trusts the gyro more.
+    pub time_constant: f32,
+    euler: Euler
+}
+
+impl Complementary {
+    pub fn new(time_constant: f32) -> Self {
+        Complementary {
+            time_constant,
+            euler: Euler::default()
+        }
+    }
+}
+
+impl Default for Complementary {
+    fn default() -> Self {
+        Complementary::new(0.5)
+    }
+}
+
+impl AttitudeEstimator for Complementary {
+    fn update(&mut self, sample: &ScaledSample, dt: f32) {
+        let [p, q, r] = sample.gyro;
+        let Euler { roll, pitch, yaw } = self.euler;
+        let (sr, cr) = (sinf(roll), cosf(roll));
+        let cp = cosf(pitch).max(1e-3);
+        let gyro_roll = roll + (p + (sr * q + cr * r) * tanf(pitch)) * dt;
+        let gyro_pitch = pitch + (cr * q - sr * r) * dt;
+        let gyro_yaw = yaw + (sr * q + cr * r) / cp * dt;
+
+        let [ax, ay, az] = sample.accel;
+        let alpha = self.time_constant / (self.time_constant + dt);
+        self.euler = match unit(sample.accel) {
+            Some(_) => Euler {
+                roll: alpha * gyro_roll + (1.0 - alpha) * atan2f(ay, az),
+                pitch: alpha * gyro_pitch + (1.0 - alpha) * atan2f(-ax, sqrtf(ay * ay + az * az)),
+                yaw: gyro_yaw
+            },
+            None => Euler { roll: gyro_roll, pitch: gyro_pitch, yaw: gyro_yaw }
+        };
+        self.euler.yaw = atan2f(sinf(self.euler.yaw), cosf(self.euler.yaw));
+    }
+
+    fn quaternion(&self) -> Quaternion {
+        Quaternion::from_euler(self.euler)
+    }
+
+    fn euler(&self) -> Euler {
+        self.euler
+    }
+
+    fn reset(&mut self) {
+        self.euler = Euler::default();
+    }
+}
+
+/// Mahony's nonlinear complementary filter on SO(3): a PI controller steers the gyro
+/// towards agreement with the measured gravity direction.
+#[cfg(feature = "attitude-mahony")]
+#[derive(Debug, Copy, Clone)]
+pub struct Mahony {
+    pub kp: f32,
+    pub ki: f32,
+    integral: [f32; 3],
+    q: Quaternion
+}
+
+#[cfg(feature = "attitude-mahony")]
+impl Mahony {
+    pub fn new(kp: f32, ki: f32) -> Self {
+        Mahony {
+            kp,
+            ki,
+            integral: [0.0; 3],
+            q: Quaternion::IDENTITY
+        }
+    }
+
+    /// Estimated gyro bias in rad/s.
+    pub fn gyro_bias(&self) -> [f32; 3] {
+        self.integral.map(|v| -v)
+    }
+}
+
+#[cfg(feature = "attitude-mahony")]
+impl Default for Mahony {
+    fn default() -> Self {
+        Mahony::new(1.0, 0.05)
+    }
+}
+
+#[cfg(feature = "attitude-mahony")]
+impl AttitudeEstimator for Mahony {
+    fn update(&mut self, sample: &ScaledSample, dt: f32) {
+        let mut gyro = sample.gyro;
+        if let Some(accel) = unit(sample.accel) {
+            let error = cross(accel, self.q.gravity());
+            for axis in 0..3 {
+                self.integral[axis] += self.ki * error[axis] * dt;
+                gyro[axis] += self.kp * error[axis] + self.integral[axis];
+            }
+        }
+        self.q = self.q.integrate(gyro, dt);
+    }
+
+    fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    fn reset(&mut self) {
+        self.integral = [0.0; 3];
+        self.q = Quaternion::IDENTITY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpu6050::STANDARD_GRAVITY;
+
+    const DT: f32 = 0.002;
+
+    /// What an ideal IMU at attitude `q` turning at body rates `gyro` reads.
+    fn sample(q: Quaternion, gyro: [f32; 3]) -> ScaledSample {
+        ScaledSample {
+            accel: q.gravity().map(|g| g * STANDARD_GRAVITY),
+            temp: 25.0,
+            gyro
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{actual} differs from {expected} by more than {tolerance}");
+    }
+
+    /// Rolls, pitches and yaws through a smooth manoeuvre and returns the true attitude
+    /// alongside the estimate after `seconds`.
+    fn fly(estimator: &mut impl AttitudeEstimator, bias: [f32; 3], seconds: f32) -> (Euler, Euler) {
+        let mut truth = Quaternion::IDENTITY;
+        let steps = (seconds / DT) as usize;
+        for step in 0..steps {
+            let t = step as f32 * DT;
+            let rates = [0.6 * cosf(0.9 * t), 0.4 * cosf(0.7 * t + 1.0), 0.3];
+            let gyro = [rates[0] + bias[0], rates[1] + bias[1], rates[2] + bias[2]];
+            estimator.update(&sample(truth, gyro), DT);
+            truth = truth.integrate(rates, DT);
+        }
+        (truth.to_euler(), estimator.euler())
+    }
+
+    #[test]
+    fn euler_round_trips() {
+        let euler = Euler { roll: 0.3, pitch: -0.4, yaw: 2.0 };
+        let back = Quaternion::from_euler(euler).to_euler();
+        assert_close(back.roll, euler.roll, 1e-5);
+        assert_close(back.pitch, euler.pitch, 1e-5);
+        assert_close(back.yaw, euler.yaw, 1e-5);
+    }
+
+    #[test]
+    fn gravity_matches_body_frame_convention() {
+        // Positive roll lifts the left side, so gravity reads towards +Y.
+        let rolled = Quaternion::from_euler(Euler { roll: 0.5, pitch: 0.0, yaw: 0.0 }).gravity();
+        assert!(rolled[1] > 0.0);
+        // Positive pitch lowers the nose, so gravity reads towards -X.
+        let pitched = Quaternion::from_euler(Euler { roll: 0.0, pitch: 0.5, yaw: 0.0 }).gravity();
+        assert!(pitched[0] < 0.0);
+    }
+
+    #[test]
+    fn complementary_levels_out_from_static_tilt() {
+        let truth = Euler { roll: 0.4, pitch: -0.3, yaw: 0.0 };
+        let mut estimator = Complementary::default();
+        for _ in 0..(5.0 / DT) as usize {
+            estimator.update(&sample(Quaternion::from_euler(truth), [0.0; 3]), DT);
+        }
+        assert_close(estimator.euler().roll, truth.roll, 0.01);
+        assert_close(estimator.euler().pitch, truth.pitch, 0.01);
+    }
+
+    #[test]
+    fn complementary_tracks_motion() {
+        let (truth, estimate) = fly(&mut Complementary::default(), [0.0; 3], 20.0);
+        assert_close(estimate.roll, truth.roll, 0.02);
+        assert_close(estimate.pitch, truth.pitch, 0.02);
+    }
+
+    #[cfg(feature = "attitude-mahony")]
+    #[test]
+    fn mahony_converges_from_wrong_start() {
+        let truth = Quaternion::from_euler(Euler { roll: -0.5, pitch: 0.6, yaw: 0.0 });
+        let mut estimator = Mahony::default();
+        for _ in 0..(40.0 / DT) as usize {
+            estimator.update(&sample(truth, [0.0; 3]), DT);
+        }
+        assert_close(estimator.euler().roll, -0.5, 0.01);
+        assert_close(estimator.euler().pitch, 0.6, 0.01);
+    }
+
+    #[cfg(feature = "attitude-mahony")]
+    #[test]
+    fn mahony_learns_gyro_bias_during_motion() {
+        let bias = [0.02, -0.03, 0.0];
+        let mut estimator = Mahony::default();
+        let (truth, estimate) = fly(&mut estimator, bias, 120.0);
+        assert_close(estimate.roll, truth.roll, 0.02);
+        assert_close(estimate.pitch, truth.pitch, 0.02);
+        assert_close(estimator.gyro_bias()[0], bias[0], 0.005);
+        assert_close(estimator.gyro_bias()[1], bias[1], 0.005);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::errors::{DroneError, Result};
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{Error as _, InputPin, OutputPin};
+
+/// How long a single transaction may take and how transient failures are retried.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BusPolicy {
+    /// Allowance on top of the time the transferred bytes take on the wire.
+    pub timeout: Duration,
+    /// SCL frequency in Hz, used to scale the timeout with the transfer length.
+    pub frequency: u32,
+    pub retries: u8,
+    /// Delay before the first retry, growing linearly with each further attempt.
+    pub backoff: Duration
+}
+
+impl BusPolicy {
+    /// Timeout for a transaction moving `bytes` bytes of nine clocks each, counting the ACK.
+    pub fn timeout_for(&self, bytes: usize) -> Duration {
+        let clocks = bytes as u64 * 9;
+        self.timeout + Duration::from_micros(clocks * 1_000_000 / self.frequency.max(1) as u64)
+    }
+}
+
+impl Default for BusPolicy {
+    fn default() -> Self {
+        BusPolicy {
+            timeout: Duration::from_millis(5),
+            frequency: 400_000,
+            retries: 2,
+            backoff: Duration::from_micros(200)
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub struct BusStats {
+    pub transactions: u32,
+    pub retries: u32,
+    pub timeouts: u32,
+    pub failures: u32
+}
+
+const RECOVERY_PULSES: usize = 9;
+
+/// Releases a slave holding SDA low by clocking SCL until it lets go, then issues a STOP.
+///
+/// The pins must be detached from the I2C peripheral and driven open-drain, e.g. by dropping
+/// the `I2c` and using `embassy_rp::gpio::Flex` or `OutputOpenDrain`. `half_period` of 5 µs
+/// gives the standard 100 kHz clock.
+pub async fn recover_bus<SCL, SDA>(scl: &mut SCL, sda: &mut SDA, half_period: Duration) -> Result<()>
+where
+    SCL: OutputPin,
+    SDA: InputPin + OutputPin
+{
+    sda.set_high().map_err(|e| DroneError::Gpio(e.kind()))?;
+    for _ in 0..RECOVERY_PULSES {
+        if sda.is_high().map_err(|e| DroneError::Gpio(e.kind()))? {
+            break
+        }
+        scl.set_low().map_err(|e| DroneError::Gpio(e.kind()))?;
+        Timer::after(half_period).await;
+        scl.set_high().map_err(|e| DroneError::Gpio(e.kind()))?;
+        Timer::after(half_period).await;
+    }
+
+    scl.set_low().map_err(|e| DroneError::Gpio(e.kind()))?;
+    sda.set_low().map_err(|e| DroneError::Gpio(e.kind()))?;
+    Timer::after(half_period).await;
+    scl.set_high().map_err(|e| DroneError::Gpio(e.kind()))?;
+    Timer::after(half_period).await;
+    sda.set_high().map_err(|e| DroneError::Gpio(e.kind()))?;
+    Timer::after(half_period).await;
+
+    if sda.is_low().map_err(|e| DroneError::Gpio(e.kind()))? {
+        return Err(DroneError::BusStuck)
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_scales_with_transfer_length() {
+        let policy = BusPolicy::default();
+        assert_eq!(policy.timeout_for(0), policy.timeout);
+        // A full FIFO burst takes about 23 ms on the wire at 400 kHz.
+        assert_eq!(policy.timeout_for(1024), policy.timeout + Duration::from_micros(23_040));
+        let slow = BusPolicy { frequency: 100_000, ..policy };
+        assert_eq!(slow.timeout_for(2), policy.timeout + Duration::from_micros(180));
+    }
+}
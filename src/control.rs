@@ -0,0 +1,315 @@
+use crate::attitude::Euler;
+use core::f32::consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Feed-forward on the setpoint.
+    pub kff: f32,
+    /// Bound on the integral contribution to the output.
+    pub integral_limit: f32,
+    pub output_limit: f32,
+    /// Cutoff of the low-pass on the derivative term, 0 disables it.
+    pub d_cutoff_hz: f32
+}
+
+impl Default for PidGains {
+    fn default() -> Self {
+        PidGains {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 0.0,
+            kff: 0.0,
+            integral_limit: f32::INFINITY,
+            output_limit: f32::INFINITY,
+            d_cutoff_hz: 0.0
+        }
+    }
+}
+
+/// PID with derivative on measurement, a filtered D-term and clamping anti-windup.
+#[derive(Debug, Copy, Clone)]
+pub struct Pid {
+    pub gains: PidGains,
+    integral: f32,
+    derivative: f32,
+    last_measurement: Option<f32>
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Self {
+        Pid {
+            gains,
+            integral: 0.0,
+            derivative: 0.0,
+            last_measurement: None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.derivative = 0.0;
+        self.last_measurement = None;
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: f32) -> f32 {
+        let gains = self.gains;
+        let error = setpoint - measurement;
+        if dt <= 0.0 {
+            return (gains.kp * error + self.integral + gains.kff * setpoint)
+                .clamp(-gains.output_limit, gains.output_limit)
+        }
+
+        // Differentiating the measurement keeps setpoint steps from kicking the output.
+        let raw_derivative = match self.last_measurement {
+            Some(last) => -(measurement - last) / dt,
+            None => 0.0
+        };
+        self.last_measurement = Some(measurement);
+        let alpha = match gains.d_cutoff_hz {
+            hz if hz > 0.0 => {
+                let rc = 1.0 / (2.0 * PI * hz);
+                dt / (rc + dt)
+            }
+            _ => 1.0
+        };
+        self.derivative += alpha * (raw_derivative - self.derivative);
+
+        let p = gains.kp * error;
+        let d = gains.kd * self.derivative;
+        let ff = gains.kff * setpoint;
+        let integral = (self.integral + gains.ki * error * dt)
+            .clamp(-gains.integral_limit, gains.integral_limit);
+        let unsaturated = p + integral + d + ff;
+        // Only integrate when it does not push further into saturation.
+        let winding_up = (unsaturated > gains.output_limit && error > 0.0)
+            || (unsaturated < -gains.output_limit && error < 0.0);
+        if !winding_up {
+            self.integral = integral;
+        }
+        (p + self.integral + d + ff).clamp(-gains.output_limit, gains.output_limit)
+    }
+}
+
+/// Wraps an angle to `(-π, π]`.
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = libm::remainderf(angle, 2.0 * PI);
+    if wrapped <= -PI { wrapped + 2.0 * PI } else { wrapped }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Setpoint {
+    /// Self-level: target roll, pitch and heading in radians.
+    Angle(Euler),
+    /// Acro: target body rates in rad/s.
+    Rate([f32; 3])
+}
+
+/// Roll, pitch and yaw gains for both loops of [`AttitudeController`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ControllerGains {
+    /// Angle loops, output is a rate setpoint in rad/s.
+    pub angle: [PidGains; 3],
+    /// Rate loops, output is a normalized torque command in `[-1, 1]`.
+    pub rate: [PidGains; 3]
+}
+
+impl Default for ControllerGains {
+    fn default() -> Self {
+        let angle = |max_rate: f32| PidGains {
+            kp: 6.0,
+            output_limit: max_rate,
+            ..PidGains::default()
+        };
+        let rate = |kp: f32, ki: f32, kd: f32| PidGains {
+            kp,
+            ki,
+            kd,
+            kff: 0.0,
+            integral_limit: 0.3,
+            output_limit: 1.0,
+            d_cutoff_hz: 80.0
+        };
+        ControllerGains {
+            angle: [angle(3.5), angle(3.5), angle(2.0)],
+            rate: [rate(0.08, 0.3, 0.002), rate(0.08, 0.3, 0.002), rate(0.15, 0.2, 0.0)]
+        }
+    }
+}
+
+/// Cascaded controller: angle errors set body rates, rate errors set torque commands.
+#[derive(Debug, Copy, Clone)]
+pub struct AttitudeController {
+    angle: [Pid; 3],
+    rate: [Pid; 3]
+}
+
+impl AttitudeController {
+    pub fn new(gains: ControllerGains) -> Self {
+        AttitudeController {
+            angle: gains.angle.map(Pid::new),
+            rate: gains.rate.map(Pid::new)
+        }
+    }
+
+    pub fn set_gains(&mut self, gains: ControllerGains) {
+        for axis in 0..3 {
+            self.angle[axis].gains = gains.angle[axis];
+            self.rate[axis].gains = gains.rate[axis];
+        }
+    }
+
+    /// Clears integrators and derivative state, e.g. while disarmed.
+    pub fn reset(&mut self) {
+        self.angle.iter_mut().chain(self.rate.iter_mut()).for_each(Pid::reset);
+    }
+
+    /// Returns roll, pitch and yaw torque commands in `[-1, 1]`.
+    pub fn update(&mut self, setpoint: Setpoint, attitude: &Euler, gyro: [f32; 3], dt: f32) -> [f32; 3] {
+        let rates = match setpoint {
+            Setpoint::Rate(rates) => {
+                self.angle.iter_mut().for_each(Pid::reset);
+                rates
+            }
+            Setpoint::Angle(target) => {
+                let target = [target.roll, target.pitch, target.yaw];
+                let measured = [attitude.roll, attitude.pitch, attitude.yaw];
+                let mut rates = [0.0; 3];
+                for axis in 0..3 {
+                    // Shift the setpoint next to the measurement so heading errors take the short way round.
+                    let setpoint = measured[axis] + wrap_angle(target[axis] - measured[axis]);
+                    rates[axis] = self.angle[axis].update(setpoint, measured[axis], dt);
+                }
+                rates
+            }
+        };
+        self.update_rate(rates, gyro, dt)
+    }
+
+    pub fn update_rate(&mut self, rates: [f32; 3], gyro: [f32; 3], dt: f32) -> [f32; 3] {
+        let mut torque = [0.0; 3];
+        for axis in 0..3 {
+            torque[axis] = self.rate[axis].update(rates[axis], gyro[axis], dt);
+        }
+        torque
+    }
+}
+
+impl Default for AttitudeController {
+    fn default() -> Self {
+        AttitudeController::new(ControllerGains::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.002;
+
+    /// Small-angle rigid body: torque commands pass a first-order motor lag and set the
+    /// angular acceleration; `disturbance` is a constant external torque in command units.
+    struct RigidBody {
+        /// Angular acceleration at full command, rad/s².
+        authority: [f32; 3],
+        motor_time_constant: f32,
+        disturbance: [f32; 3],
+        torque: [f32; 3],
+        rates: [f32; 3],
+        attitude: Euler
+    }
+
+    impl RigidBody {
+        fn new() -> Self {
+            RigidBody {
+                authority: [250.0, 250.0, 60.0],
+                motor_time_constant: 0.02,
+                disturbance: [0.0; 3],
+                torque: [0.0; 3],
+                rates: [0.0; 3],
+                attitude: Euler::default()
+            }
+        }
+
+        fn step(&mut self, command: [f32; 3], dt: f32) {
+            for (axis, command) in command.into_iter().enumerate() {
+                self.torque[axis] += (command - self.torque[axis]) * dt / self.motor_time_constant;
+                let accel = (self.torque[axis] + self.disturbance[axis]) * self.authority[axis];
+                self.rates[axis] += accel * dt;
+            }
+            self.attitude.roll += self.rates[0] * dt;
+            self.attitude.pitch += self.rates[1] * dt;
+            self.attitude.yaw = wrap_angle(self.attitude.yaw + self.rates[2] * dt);
+        }
+
+        fn fly(&mut self, controller: &mut AttitudeController, setpoint: Setpoint, seconds: f32) {
+            for _ in 0..(seconds / DT) as usize {
+                let command = controller.update(setpoint, &self.attitude, self.rates, DT);
+                assert!(command.iter().all(|c| c.abs() <= 1.0));
+                self.step(command, DT);
+            }
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{actual} differs from {expected} by more than {tolerance}");
+    }
+
+    #[test]
+    fn wrap_angle_takes_short_way() {
+        assert_close(wrap_angle(3.0 * PI), PI, 1e-5);
+        assert_close(wrap_angle(-PI), PI, 1e-5);
+        assert_close(wrap_angle(-3.0 - 3.0), 2.0 * PI - 6.0, 1e-5);
+    }
+
+    #[test]
+    fn pid_integral_does_not_wind_up_in_saturation() {
+        let mut pid = Pid::new(PidGains { kp: 1.0, ki: 10.0, output_limit: 1.0, ..PidGains::default() });
+        for _ in 0..1000 {
+            assert_eq!(pid.update(5.0, 0.0, DT), 1.0);
+        }
+        assert!(pid.integral() < 1.0);
+        assert!(pid.update(0.0, 0.0, DT).abs() < 1.0);
+    }
+
+    #[test]
+    fn rate_mode_tracks_step() {
+        let mut body = RigidBody::new();
+        let mut controller = AttitudeController::default();
+        body.fly(&mut controller, Setpoint::Rate([2.0, -1.0, 1.0]), 2.0);
+        assert_close(body.rates[0], 2.0, 0.05);
+        assert_close(body.rates[1], -1.0, 0.05);
+        assert_close(body.rates[2], 1.0, 0.05);
+    }
+
+    #[test]
+    fn angle_mode_settles_against_disturbance() {
+        let mut body = RigidBody::new();
+        body.disturbance = [0.05, -0.1, 0.02];
+        let mut controller = AttitudeController::default();
+        let target = Euler { roll: 0.3, pitch: -0.2, yaw: 0.5 };
+        body.fly(&mut controller, Setpoint::Angle(target), 4.0);
+        assert_close(body.attitude.roll, target.roll, 0.01);
+        assert_close(body.attitude.pitch, target.pitch, 0.01);
+        assert_close(body.attitude.yaw, target.yaw, 0.01);
+        assert!(body.rates.iter().all(|r| r.abs() < 0.05));
+    }
+
+    #[test]
+    fn heading_turns_through_pi() {
+        let mut body = RigidBody::new();
+        body.attitude.yaw = 3.0;
+        let mut controller = AttitudeController::default();
+        let target = Euler { roll: 0.0, pitch: 0.0, yaw: -3.0 };
+        body.fly(&mut controller, Setpoint::Angle(target), 0.1);
+        assert!(body.rates[2] > 0.0);
+        body.fly(&mut controller, Setpoint::Angle(target), 4.0);
+        assert_close(wrap_angle(body.attitude.yaw - target.yaw), 0.0, 0.01);
+    }
+}
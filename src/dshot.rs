@@ -0,0 +1,484 @@
+use crate::esc::{MotorCommands, MotorOutput};
+use crate::mixer::MAX_MOTORS;
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::dma::Channel;
+use embassy_rp::gpio::{Level, Pull};
+use embassy_rp::pio::{Common, Config, Direction, Instance, Pin, ShiftConfig, ShiftDirection, StateMachine};
+use embassy_rp::{into_ref, Peripheral, PeripheralRef};
+use embassy_time::Timer;
+use fixed::types::extra::U8;
+use fixed::FixedU32;
+
+/// Motors driven in lockstep by one state machine, on consecutive pins.
+pub const DSHOT_MOTORS: usize = 4;
+pub const THROTTLE_MIN: u16 = 48;
+pub const THROTTLE_MAX: u16 = 2047;
+/// State machine cycles per DShot bit.
+const CYCLES_PER_BIT: u32 = 16;
+/// Telemetry samples per DShot bit; each sample holds one nibble of pin levels.
+const SAMPLES_PER_BIT: u32 = 4;
+/// Telemetry window in words, 64 DShot bit times covers the ~30 µs turnaround plus the reply.
+const TELEMETRY_WORDS: usize = 32;
+const TELEMETRY_SAMPLES: u32 = TELEMETRY_WORDS as u32 * 8;
+/// Telemetry replies run at 5/4 of the command bitrate.
+const SAMPLES_PER_TELEMETRY_BIT: f32 = SAMPLES_PER_BIT as f32 * 4.0 / 5.0;
+const TELEMETRY_BITS: u32 = 21;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum DshotSpeed {
+    Dshot150,
+    Dshot300,
+    Dshot600
+}
+
+impl DshotSpeed {
+    pub fn bitrate(self) -> u32 {
+        match self {
+            DshotSpeed::Dshot150 => 150_000,
+            DshotSpeed::Dshot300 => 300_000,
+            DshotSpeed::Dshot600 => 600_000
+        }
+    }
+}
+
+/// Special commands, sent in place of a throttle value while the motors are stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum Command {
+    MotorStop = 0,
+    Beep1 = 1,
+    Beep2 = 2,
+    Beep3 = 3,
+    Beep4 = 4,
+    Beep5 = 5,
+    EscInfo = 6,
+    SpinDirection1 = 7,
+    SpinDirection2 = 8,
+    Mode3dOff = 9,
+    Mode3dOn = 10,
+    SaveSettings = 12,
+    SpinDirectionNormal = 20,
+    SpinDirectionReversed = 21
+}
+
+impl Command {
+    pub fn value(self) -> u16 {
+        self as u16
+    }
+
+    /// ESCs only act on settings commands received several times in a row.
+    pub fn repeats(self) -> u8 {
+        match self {
+            Command::SpinDirection1
+            | Command::SpinDirection2
+            | Command::Mode3dOff
+            | Command::Mode3dOn
+            | Command::SaveSettings
+            | Command::SpinDirectionNormal
+            | Command::SpinDirectionReversed => 6,
+            _ => 1
+        }
+    }
+}
+
+/// Maps a normalized command to a throttle value; zero and below stop the motor.
+pub fn throttle_value(command: f32) -> u16 {
+    if command.is_nan() || command <= 0.0 {
+        return Command::MotorStop.value()
+    }
+    let span = (THROTTLE_MAX - THROTTLE_MIN) as f32;
+    THROTTLE_MIN + (command.min(1.0) * span + 0.5) as u16
+}
+
+/// Builds a 16 bit frame from an 11 bit value, the telemetry request bit and the checksum.
+/// Bidirectional ESCs expect the checksum inverted.
+pub fn encode_frame(value: u16, telemetry: bool, bidirectional: bool) -> u16 {
+    let packet = (value & 0x07ff) << 1 | telemetry as u16;
+    let crc = (packet ^ packet >> 4 ^ packet >> 8) & 0x0f;
+    let crc = if bidirectional { !crc & 0x0f } else { crc };
+    packet << 4 | crc
+}
+
+/// Packs one frame per motor into the two FIFO words the state machine shifts out,
+/// one nibble per bit time with motor `n` on bit `n`, MSB first.
+pub fn interleave(frames: &[u16; DSHOT_MOTORS]) -> [u32; 2] {
+    let mut words = [0u32; 2];
+    for bit in 0..16 {
+        let mut nibble = 0u32;
+        for (motor, frame) in frames.iter().enumerate() {
+            nibble |= ((frame >> (15 - bit) & 1) as u32) << motor;
+        }
+        words[bit / 8] |= nibble << ((7 - bit % 8) * 4);
+    }
+    words
+}
+
+/// Line levels of one motor from the sampled telemetry window.
+pub fn motor_levels(words: &[u32], motor: usize) -> impl Iterator<Item = bool> + '_ {
+    words.iter().flat_map(move |word| (0..8).rev().map(move |nibble| word >> (nibble * 4 + motor) & 1 != 0))
+}
+
+/// Recovers the raw 21 bit reply from oversampled line levels by measuring run lengths
+/// from the start bit, so small clock differences between ESC and sampler do not accumulate.
+pub fn decode_line(levels: impl Iterator<Item = bool>, samples_per_bit: f32) -> Option<u32> {
+    let mut levels = levels.skip_while(|&level| level);
+    levels.next()?;
+    let mut value = 0u32;
+    let mut bits = 0u32;
+    let mut level = false;
+    let mut run = 1u32;
+    for sample in levels {
+        if sample == level {
+            run += 1;
+            continue
+        }
+        let len = ((run as f32 / samples_per_bit + 0.5) as u32).max(1);
+        bits += len;
+        if bits > TELEMETRY_BITS {
+            return None
+        }
+        value = value << len | if level { (1 << len) - 1 } else { 0 };
+        level = sample;
+        run = 1;
+    }
+    // The last run merges into the idle level.
+    let len = TELEMETRY_BITS - bits;
+    Some(value << len | if level { (1 << len) - 1 } else { 0 })
+}
+
+/// 4b/5b group code, indexed by nibble.
+const GCR: [u8; 16] = [
+    0x19, 0x1b, 0x12, 0x13, 0x1d, 0x15, 0x16, 0x17,
+    0x1a, 0x09, 0x0a, 0x0b, 0x1e, 0x0d, 0x0e, 0x0f
+];
+
+/// Turns the 21 line bits (start bit first, one transition per GCR one) into the 16 bit reply.
+pub fn decode_gcr(line: u32) -> Option<u16> {
+    let gcr = (line ^ line >> 1) & 0x000f_ffff;
+    let mut frame = 0u16;
+    for quintet in (0..4).rev() {
+        let code = (gcr >> (quintet * 5) & 0x1f) as u8;
+        let nibble = GCR.iter().position(|&c| c == code)?;
+        frame = frame << 4 | nibble as u16;
+    }
+    Some(frame)
+}
+
+/// Checks the reply checksum and converts the encoded period to electrical RPM.
+pub fn decode_erpm(frame: u16) -> Option<u32> {
+    let crc = frame ^ frame >> 4 ^ frame >> 8 ^ frame >> 12;
+    if crc & 0x0f != 0x0f {
+        return None
+    }
+    let value = frame >> 4;
+    if value == 0x0fff {
+        return Some(0)
+    }
+    // Period in µs as a 9 bit mantissa with a 3 bit left shift.
+    let period = ((value & 0x01ff) as u32) << (value >> 9);
+    if period == 0 {
+        return None
+    }
+    Some(60_000_000 / period)
+}
+
+pub fn erpm_to_rpm(erpm: u32, pole_pairs: u8) -> u32 {
+    erpm / pole_pairs.max(1) as u32
+}
+
+/// DShot output on up to [`DSHOT_MOTORS`] consecutive pins of one PIO state machine.
+///
+/// In bidirectional mode the lines idle high, the checksum is inverted and after every
+/// frame the pins are released and sampled so each ESC can reply with its eRPM.
+pub struct Dshot<'d, P: Instance, const SM: usize, D: Channel> {
+    sm: StateMachine<'d, P, SM>,
+    dma: PeripheralRef<'d, D>,
+    speed: DshotSpeed,
+    bidirectional: bool,
+    motors: usize,
+    erpm: [Option<u32>; DSHOT_MOTORS],
+    samples: [u32; TELEMETRY_WORDS]
+}
+
+impl<'d, P: Instance, const SM: usize, D: Channel> Dshot<'d, P, SM, D> {
+    pub fn new<const N: usize>(
+        common: &mut Common<'d, P>,
+        mut sm: StateMachine<'d, P, SM>,
+        mut pins: [Pin<'d, P>; N],
+        dma: impl Peripheral<P = D> + 'd,
+        speed: DshotSpeed,
+        bidirectional: bool
+    ) -> Self {
+        assert!(N > 0 && N <= DSHOT_MOTORS);
+        into_ref!(dma);
+        let mut config = Config::default();
+        if bidirectional {
+            // 16 cycles per bit: 6 low, 6 low for a one, 4 high; then sample every 4 cycles.
+            let program = pio_proc::pio_asm!(
+                ".wrap_target",
+                "    set y, 15",
+                "    set pindirs, 15",
+                "bit:",
+                "    out x, 4",
+                "    mov pins, null [5]",
+                "    mov pins, !x [5]",
+                "    mov pins, !null [1]",
+                "    jmp y-- bit",
+                "    out y, 32",
+                "    set pindirs, 0",
+                "sample:",
+                "    in pins, 4 [2]",
+                "    jmp y-- sample",
+                ".wrap"
+            );
+            config.use_program(&common.load_program(&program.program), &[]);
+            for pin in pins.iter_mut() {
+                pin.set_pull(Pull::Up);
+            }
+        } else {
+            // 16 cycles per bit: 6 high, 6 high for a one, 4 low.
+            let program = pio_proc::pio_asm!(
+                ".wrap_target",
+                "    out x, 4",
+                "    mov pins, !null [5]",
+                "    mov pins, x [5]",
+                "    mov pins, null [2]",
+                ".wrap"
+            );
+            config.use_program(&common.load_program(&program.program), &[]);
+        }
+        let refs: [&Pin<'d, P>; N] = core::array::from_fn(|i| &pins[i]);
+        config.set_out_pins(&refs);
+        config.set_set_pins(&refs);
+        config.set_in_pins(&refs);
+        config.shift_out = ShiftConfig {
+            threshold: 32,
+            direction: ShiftDirection::Left,
+            auto_fill: true
+        };
+        config.shift_in = ShiftConfig {
+            threshold: 32,
+            direction: ShiftDirection::Left,
+            auto_fill: true
+        };
+        let ticks = clk_sys_freq() as u64 * 256 / (speed.bitrate() * CYCLES_PER_BIT) as u64;
+        config.clock_divider = FixedU32::<U8>::from_bits(ticks as u32);
+        sm.set_config(&config);
+        sm.set_pins(Level::from(bidirectional), &refs);
+        sm.set_pin_dirs(Direction::Out, &refs);
+        sm.set_enable(true);
+
+        Dshot {
+            sm,
+            dma,
+            speed,
+            bidirectional,
+            motors: N,
+            erpm: [None; DSHOT_MOTORS],
+            samples: [0; TELEMETRY_WORDS]
+        }
+    }
+
+    pub fn speed(&self) -> DshotSpeed {
+        self.speed
+    }
+
+    pub fn motor_count(&self) -> usize {
+        self.motors
+    }
+
+    /// Electrical RPM from the last valid reply of each motor, bidirectional mode only.
+    pub fn erpm(&self) -> [Option<u32>; DSHOT_MOTORS] {
+        self.erpm
+    }
+
+    pub fn rpm(&self, pole_pairs: u8) -> [Option<u32>; DSHOT_MOTORS] {
+        self.erpm.map(|erpm| erpm.map(|erpm| erpm_to_rpm(erpm, pole_pairs)))
+    }
+
+    /// Sends one throttle frame per motor from normalized commands in `[0, 1]`.
+    pub async fn write(&mut self, commands: &MotorCommands) {
+        let frames = core::array::from_fn(|motor| {
+            let value = commands.get(motor).map_or(0, |&command| throttle_value(command));
+            encode_frame(value, false, self.bidirectional)
+        });
+        self.send(&frames).await;
+    }
+
+    pub async fn stop(&mut self) {
+        self.write(&[0.0; MAX_MOTORS]).await;
+    }
+
+    /// Sends a special command to every motor, repeated as often as the ESCs require.
+    pub async fn command(&mut self, command: Command) {
+        let frame = encode_frame(command.value(), true, self.bidirectional);
+        for _ in 0..command.repeats() {
+            self.send(&[frame; DSHOT_MOTORS]).await;
+            Timer::after_millis(1).await;
+        }
+    }
+
+    async fn send(&mut self, frames: &[u16; DSHOT_MOTORS]) {
+        let [first, second] = interleave(frames);
+        if !self.bidirectional {
+            let tx = self.sm.tx();
+            tx.wait_push(first).await;
+            tx.wait_push(second).await;
+            return
+        }
+
+        let (rx, tx) = self.sm.rx_tx();
+        let transfer = rx.dma_pull(self.dma.reborrow(), &mut self.samples);
+        tx.wait_push(first).await;
+        tx.wait_push(second).await;
+        tx.wait_push(TELEMETRY_SAMPLES - 1).await;
+        transfer.await;
+
+        for motor in 0..self.motors {
+            let levels = motor_levels(&self.samples, motor);
+            let erpm = decode_line(levels, SAMPLES_PER_TELEMETRY_BIT)
+                .and_then(decode_gcr)
+                .and_then(decode_erpm);
+            // Keep the last good reading over an occasional corrupted reply.
+            if erpm.is_some() {
+                self.erpm[motor] = erpm;
+            }
+        }
+    }
+}
+
+impl<P: Instance, const SM: usize, D: Channel> MotorOutput for Dshot<'_, P, SM, D> {
+    async fn write(&mut self, commands: &MotorCommands) {
+        Dshot::write(self, commands).await;
+    }
+
+    async fn stop(&mut self) {
+        Dshot::stop(self).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reply an ESC sends for `period_us`, before GCR encoding.
+    fn reply_frame(period_us: u32) -> u16 {
+        let mut period = period_us;
+        let mut exponent = 0;
+        while period > 0x01ff {
+            period >>= 1;
+            exponent += 1;
+        }
+        let value = (exponent << 9 | period) as u16;
+        let crc = !(value ^ value >> 4 ^ value >> 8) & 0x0f;
+        value << 4 | crc
+    }
+
+    /// GCR-encodes `frame` and returns the 21 line bits, start bit first.
+    fn line_bits(frame: u16) -> u32 {
+        let gcr = (0..4).rev().fold(0u32, |gcr, i| gcr << 5 | GCR[(frame >> (i * 4) & 0x0f) as usize] as u32);
+        let mut line = 0u32;
+        let mut level = 0u32;
+        for bit in (0..20).rev() {
+            level ^= gcr >> bit & 1;
+            line = line << 1 | level;
+        }
+        line
+    }
+
+    /// Oversamples each motor's line into the telemetry window the state machine captures.
+    fn sample_lines(lines: &[u32; DSHOT_MOTORS], samples_per_bit: f32, idle: usize) -> [u32; TELEMETRY_WORDS] {
+        let mut words = [0u32; TELEMETRY_WORDS];
+        for sample in 0..TELEMETRY_WORDS * 8 {
+            for (motor, &line) in lines.iter().enumerate() {
+                let bit = (sample as f32 - idle as f32) / samples_per_bit;
+                let high = if sample < idle || bit >= TELEMETRY_BITS as f32 {
+                    true
+                } else {
+                    line >> (TELEMETRY_BITS - 1 - bit as u32) & 1 != 0
+                };
+                words[sample / 8] |= (high as u32) << ((7 - sample % 8) * 4 + motor);
+            }
+        }
+        words
+    }
+
+    #[test]
+    fn encodes_reference_frame() {
+        assert_eq!(encode_frame(1046, false, false), 0b1000_0010_1100_0110);
+        assert_eq!(encode_frame(1046, false, true), 0b1000_0010_1100_1001);
+        assert_eq!(encode_frame(Command::Beep1.value(), true, false) >> 4, 0b0000_0000_0011);
+    }
+
+    #[test]
+    fn checksum_covers_every_value() {
+        for value in 0..=THROTTLE_MAX {
+            for telemetry in [false, true] {
+                let frame = encode_frame(value, telemetry, false);
+                assert_eq!((frame ^ frame >> 4 ^ frame >> 8 ^ frame >> 12) & 0x0f, 0);
+                let frame = encode_frame(value, telemetry, true);
+                assert_eq!((frame ^ frame >> 4 ^ frame >> 8 ^ frame >> 12) & 0x0f, 0x0f);
+                assert_eq!(frame >> 5, value);
+            }
+        }
+    }
+
+    #[test]
+    fn throttle_spans_valid_range() {
+        assert_eq!(throttle_value(0.0), 0);
+        assert_eq!(throttle_value(f32::NAN), 0);
+        assert_eq!(throttle_value(1e-6), THROTTLE_MIN);
+        assert_eq!(throttle_value(1.0), THROTTLE_MAX);
+        assert_eq!(throttle_value(2.0), THROTTLE_MAX);
+    }
+
+    #[test]
+    fn interleave_round_trips() {
+        let frames = [0x82c6, 0xffff, 0x0001, 0x5a5a];
+        let words = interleave(&frames);
+        for (motor, &frame) in frames.iter().enumerate() {
+            let bits = motor_levels(&words, motor).fold(0u16, |acc, level| acc << 1 | level as u16);
+            assert_eq!(bits, frame);
+        }
+    }
+
+    #[test]
+    fn gcr_round_trips() {
+        for frame in [0x0000, 0xffff, 0x1234, reply_frame(1000)] {
+            assert_eq!(decode_gcr(line_bits(frame)), Some(frame));
+        }
+    }
+
+    #[test]
+    fn telemetry_round_trips_with_clock_drift() {
+        let periods = [1000, 250, 4000, 16_000];
+        let lines = periods.map(|period| line_bits(reply_frame(period)));
+        for samples_per_bit in [3.1, 3.2, 3.3] {
+            for idle in [20, 23, 37] {
+                let words = sample_lines(&lines, samples_per_bit, idle);
+                for (motor, &period) in periods.iter().enumerate() {
+                    let erpm = decode_line(motor_levels(&words, motor), SAMPLES_PER_TELEMETRY_BIT)
+                        .and_then(decode_gcr)
+                        .and_then(decode_erpm);
+                    assert_eq!(erpm, Some(60_000_000 / period), "{samples_per_bit} {idle} motor {motor}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_reply_is_rejected() {
+        let frame = reply_frame(1000);
+        assert_eq!(decode_erpm(frame), Some(60_000));
+        for bit in 0..16 {
+            assert_eq!(decode_erpm(frame ^ 1 << bit), None);
+        }
+    }
+
+    #[test]
+    fn stopped_motor_reports_zero() {
+        let value = 0x0fffu16;
+        let crc = !(value ^ value >> 4 ^ value >> 8) & 0x0f;
+        assert_eq!(decode_erpm(value << 4 | crc), Some(0));
+        assert_eq!(erpm_to_rpm(60_000, 7), 8_571);
+    }
+}
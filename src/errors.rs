@@ -1,21 +1,168 @@
-use alloc::format;
-use alloc::string::String;
 use core::fmt::Debug;
-use embassy_rp::i2c::Error;
+use embassy_embedded_hal::shared_bus::I2cDeviceError;
+use embassy_rp::i2c::AbortReason;
+use embedded_hal_async::i2c::{Error as _, ErrorKind, NoAcknowledgeSource};
+use embedded_storage::nor_flash::NorFlashErrorKind;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq, defmt::Format)]
+pub enum BusError {
+    #[error("address not acknowledged")]
+    AddressNack,
+    #[error("data not acknowledged")]
+    DataNack,
+    #[error("not acknowledged")]
+    Nack,
+    #[error("arbitration lost")]
+    ArbitrationLoss,
+    #[error("bus error")]
+    Bus,
+    #[error("overrun")]
+    Overrun,
+    #[error("timed out")]
+    Timeout,
+    /// Controller-specific failure such as an invalid buffer length or an abort reason
+    /// without a generic kind.
+    #[error("controller error: {0}")]
+    Other(ErrorKind)
+}
+
+impl BusError {
+    /// Errors that may clear up on their own, as opposed to misuse of the bus.
+    pub fn is_transient(self) -> bool {
+        !matches!(self, BusError::Other(_))
+    }
+}
+
+impl From<ErrorKind> for BusError {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address) => BusError::AddressNack,
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data) => BusError::DataNack,
+            ErrorKind::NoAcknowledge(_) => BusError::Nack,
+            ErrorKind::ArbitrationLoss => BusError::ArbitrationLoss,
+            ErrorKind::Bus => BusError::Bus,
+            ErrorKind::Overrun => BusError::Overrun,
+            kind => BusError::Other(kind)
+        }
+    }
+}
+
+/// `IC_TX_ABRT_SOURCE` bit for a data byte the slave did not acknowledge.
+const ABRT_TXDATA_NOACK: u32 = 1 << 3;
+
+/// Controller errors that know more than their [`ErrorKind`], which e.g. reports every
+/// RP2040 abort other than an address NACK or lost arbitration as `Other`.
+pub trait I2cErrorExt: embedded_hal_async::i2c::Error {
+    fn bus_error(&self) -> BusError {
+        BusError::from(self.kind())
+    }
+}
+
+impl I2cErrorExt for ErrorKind {}
+
+impl I2cErrorExt for embassy_rp::i2c::Error {
+    fn bus_error(&self) -> BusError {
+        match *self {
+            embassy_rp::i2c::Error::Abort(AbortReason::Other(bits)) if bits & ABRT_TXDATA_NOACK != 0 => {
+                BusError::DataNack
+            }
+            _ => BusError::from(self.kind())
+        }
+    }
+}
+
+impl<E: I2cErrorExt> I2cErrorExt for I2cDeviceError<E> {
+    fn bus_error(&self) -> BusError {
+        match self {
+            I2cDeviceError::I2c(error) => error.bus_error(),
+            I2cDeviceError::Config => BusError::from(self.kind())
+        }
+    }
+}
+
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum DroneError {
-    #[error("I2C error: {0}")]
-    I2c(String),
-    #[error("invalid chip ID {0} (expected: {default})", default = crate::mpu6050::DEFAULT_SLAVE_ADDR)]
-    InvalidChipId(u8)
+    #[error("I2C error on register {reg:#04x}: {error}")]
+    I2c { reg: u8, error: BusError },
+    #[error("invalid chip ID {0} (expected: {default})", default = crate::mpu6050::CHIP_ID)]
+    InvalidChipId(u8),
+    #[error("FIFO overflow, buffered samples were discarded")]
+    FifoOverflow,
+    #[error("FIFO is not enabled")]
+    FifoDisabled,
+    #[error("aux slave data in the FIFO is not supported")]
+    FifoSlaveData,
+    #[error("GPIO error: {0:?}")]
+    Gpio(embedded_hal::digital::ErrorKind),
+    #[error("flash error: {0:?}")]
+    Flash(NorFlashErrorKind),
+    #[error("invalid or missing calibration data")]
+    InvalidCalibration,
+    #[error("aux I2C slave {0:#04x} did not acknowledge")]
+    AuxNack(u8),
+    #[error("aux I2C master lost arbitration addressing {0:#04x}")]
+    AuxArbitrationLost(u8),
+    #[error("aux I2C transfer to {0:#04x} timed out")]
+    AuxTimeout(u8),
+    #[error("device reset did not complete")]
+    ResetTimeout,
+    #[error("I2C bus still held low after recovery")]
+    BusStuck
+}
+
+impl defmt::Format for DroneError {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            DroneError::I2c { reg, error } => defmt::write!(f, "I2C {=u8:#04x}: {}", reg, error),
+            DroneError::InvalidChipId(id) => defmt::write!(f, "chip ID {=u8:#04x}", id),
+            DroneError::FifoOverflow => defmt::write!(f, "FIFO overflow"),
+            DroneError::FifoDisabled => defmt::write!(f, "FIFO disabled"),
+            DroneError::FifoSlaveData => defmt::write!(f, "FIFO slave data unsupported"),
+            DroneError::Gpio(kind) => defmt::write!(f, "GPIO error: {}", kind),
+            DroneError::Flash(NorFlashErrorKind::NotAligned) => defmt::write!(f, "flash not aligned"),
+            DroneError::Flash(NorFlashErrorKind::OutOfBounds) => defmt::write!(f, "flash out of bounds"),
+            DroneError::Flash(NorFlashErrorKind::Other) => defmt::write!(f, "flash error: other"),
+            DroneError::Flash(kind) => defmt::write!(f, "flash error: {}", defmt::Debug2Format(kind)),
+            DroneError::InvalidCalibration => defmt::write!(f, "invalid calibration"),
+            DroneError::AuxNack(addr) => defmt::write!(f, "aux {=u8:#04x} NACK", addr),
+            DroneError::AuxArbitrationLost(addr) => defmt::write!(f, "aux {=u8:#04x} arbitration lost", addr),
+            DroneError::AuxTimeout(addr) => defmt::write!(f, "aux {=u8:#04x} timeout", addr),
+            DroneError::ResetTimeout => defmt::write!(f, "reset timeout"),
+            DroneError::BusStuck => defmt::write!(f, "bus stuck")
+        }
+    }
 }
 
-impl From<embassy_rp::i2c::Error> for DroneError {
-    fn from(value: Error) -> Self {
-        DroneError::I2c(format!("{:?}", value))
+impl From<NorFlashErrorKind> for DroneError {
+    fn from(value: NorFlashErrorKind) -> Self {
+        DroneError::Flash(value)
     }
 }
 
-pub type Result<T> = core::result::Result<T, DroneError>;
\ No newline at end of file
+pub type Result<T> = core::result::Result<T, DroneError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_error_keeps_kind() {
+        assert_eq!(BusError::from(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)), BusError::AddressNack);
+        assert_eq!(BusError::from(ErrorKind::ArbitrationLoss), BusError::ArbitrationLoss);
+        assert_eq!(BusError::from(ErrorKind::Other), BusError::Other(ErrorKind::Other));
+        assert!(BusError::from(ErrorKind::Bus).is_transient());
+        assert!(!BusError::from(ErrorKind::Other).is_transient());
+    }
+
+    #[test]
+    fn rp_abort_bits_are_decoded() {
+        use embassy_rp::i2c::Error as RpError;
+        assert_eq!(RpError::Abort(AbortReason::Other(ABRT_TXDATA_NOACK)).bus_error(), BusError::DataNack);
+        assert_eq!(RpError::Abort(AbortReason::NoAcknowledge).bus_error(), BusError::AddressNack);
+        assert_eq!(RpError::Abort(AbortReason::Other(1 << 7)).bus_error(), BusError::Other(ErrorKind::Other));
+        assert_eq!(RpError::InvalidReadBufferLength.bus_error(), BusError::Other(ErrorKind::Other));
+        let device: I2cDeviceError<RpError> = I2cDeviceError::I2c(RpError::Abort(AbortReason::Other(ABRT_TXDATA_NOACK)));
+        assert_eq!(device.bus_error(), BusError::DataNack);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::mixer::MAX_MOTORS;
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::pwm::{Config, Pwm};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+
+/// Motor commands from the mixer, one normalized value in `[0, 1]` per motor.
+pub type MotorCommands = [f32; MAX_MOTORS];
+
+/// A motor driver fed by the flight loop, such as [`Esc`] or [`crate::dshot::Dshot`].
+#[allow(async_fn_in_trait)]
+pub trait MotorOutput {
+    /// Sends normalized commands in `[0, 1]`.
+    async fn write(&mut self, commands: &MotorCommands);
+
+    /// Sends zero throttle to every motor.
+    async fn stop(&mut self);
+
+    /// Writes commands as they arrive and stops the motors whenever none arrives within `timeout`.
+    async fn run<M: RawMutex>(&mut self, commands: &Signal<M, MotorCommands>, timeout: Duration) -> ! {
+        let mut starved = false;
+        loop {
+            match with_timeout(timeout, commands.wait()).await {
+                Ok(values) => {
+                    if starved {
+                        defmt::info!("motor commands resumed");
+                        starved = false;
+                    }
+                    self.write(&values).await;
+                }
+                Err(_) => {
+                    if !starved {
+                        defmt::warn!("motor commands stalled for {}, stopping motors", timeout);
+                        starved = true;
+                    }
+                    self.stop().await;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum EscProtocol {
+    /// Classic 1000-2000 µs servo pulses at 50-490 Hz.
+    Pwm { hz: u16 },
+    /// 125-250 µs pulses.
+    OneShot125,
+    /// 42-84 µs pulses.
+    OneShot42,
+    /// 5-25 µs pulses.
+    Multishot
+}
+
+impl EscProtocol {
+    /// Default pulse endpoints in µs.
+    pub fn pulse_range(self) -> Endpoints {
+        let (low, high) = match self {
+            EscProtocol::Pwm { .. } => (1000.0, 2000.0),
+            EscProtocol::OneShot125 => (125.0, 250.0),
+            EscProtocol::OneShot42 => (42.0, 84.0),
+            EscProtocol::Multishot => (5.0, 25.0)
+        };
+        Endpoints { low, high }
+    }
+
+    /// Update rate; the one-shot variants run as fast as their longest pulse allows.
+    pub fn frequency(self) -> u32 {
+        match self {
+            EscProtocol::Pwm { hz } => (hz as u32).clamp(50, 490),
+            EscProtocol::OneShot125 => 2_000,
+            EscProtocol::OneShot42 => 8_000,
+            EscProtocol::Multishot => 32_000
+        }
+    }
+}
+
+/// Pulse widths in µs sent for zero and full throttle.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct Endpoints {
+    pub low: f32,
+    pub high: f32
+}
+
+impl Endpoints {
+    pub fn pulse(&self, value: f32) -> f32 {
+        self.low + (self.high - self.low) * value.clamp(0.0, 1.0)
+    }
+}
+
+/// Counter settings of a slice running at the protocol's frequency.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+struct Timing {
+    divider: u8,
+    top: u16,
+    ticks_per_us: f32
+}
+
+impl Timing {
+    fn new(sys_hz: u32, frequency: u32) -> Self {
+        let ticks = sys_hz / frequency;
+        let divider = ticks.div_ceil(u16::MAX as u32 + 1).clamp(1, u8::MAX as u32);
+        let top = (ticks / divider - 1).min(u16::MAX as u32);
+        Timing {
+            divider: divider as u8,
+            top: top as u16,
+            ticks_per_us: sys_hz as f32 / divider as f32 / 1_000_000.0
+        }
+    }
+
+    fn compare(&self, pulse_us: f32) -> u16 {
+        ((pulse_us * self.ticks_per_us) as u32).min(self.top as u32 + 1) as u16
+    }
+}
+
+/// Drives two ESCs per PWM slice, motor `2n` on channel A and `2n + 1` on channel B of slice `n`.
+pub struct Esc<'d, const SLICES: usize> {
+    slices: [Pwm<'d>; SLICES],
+    configs: [Config; SLICES],
+    endpoints: [[Endpoints; 2]; SLICES],
+    protocol: EscProtocol,
+    timing: Timing
+}
+
+impl<'d, const SLICES: usize> Esc<'d, SLICES> {
+    /// Takes slices created with their output pins and starts them at the low endpoint.
+    pub fn new(slices: [Pwm<'d>; SLICES], protocol: EscProtocol) -> Self {
+        let timing = Timing::new(clk_sys_freq(), protocol.frequency());
+        let configs = core::array::from_fn(|_| {
+            let mut config = Config::default();
+            config.divider = timing.divider.into();
+            config.top = timing.top;
+            config
+        });
+        let mut esc = Esc {
+            slices,
+            configs,
+            endpoints: [[protocol.pulse_range(); 2]; SLICES],
+            protocol,
+            timing
+        };
+        esc.stop();
+        esc
+    }
+
+    pub fn protocol(&self) -> EscProtocol {
+        self.protocol
+    }
+
+    pub fn motor_count(&self) -> usize {
+        (2 * SLICES).min(MAX_MOTORS)
+    }
+
+    pub fn endpoints(&self, motor: usize) -> Endpoints {
+        self.endpoints[motor / 2][motor % 2]
+    }
+
+    /// Overrides the pulse endpoints of one motor, e.g. after an ESC throttle calibration.
+    pub fn set_endpoints(&mut self, motor: usize, endpoints: Endpoints) {
+        self.endpoints[motor / 2][motor % 2] = endpoints;
+    }
+
+    /// Sets all motors from normalized commands in `[0, 1]`.
+    pub fn write(&mut self, commands: &MotorCommands) {
+        for (slice, pwm) in self.slices.iter_mut().enumerate() {
+            let config = &mut self.configs[slice];
+            let [a, b] = self.endpoints[slice];
+            config.compare_a = self.timing.compare(a.pulse(commands.get(2 * slice).copied().unwrap_or(0.0)));
+            config.compare_b = self.timing.compare(b.pulse(commands.get(2 * slice + 1).copied().unwrap_or(0.0)));
+            pwm.set_config(config);
+        }
+    }
+
+    /// Sends the low endpoint to every motor.
+    pub fn stop(&mut self) {
+        self.write(&[0.0; MAX_MOTORS]);
+    }
+}
+
+impl<const SLICES: usize> MotorOutput for Esc<'_, SLICES> {
+    async fn write(&mut self, commands: &MotorCommands) {
+        Esc::write(self, commands);
+    }
+
+    async fn stop(&mut self) {
+        Esc::stop(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::run;
+    use embassy_futures::select::select;
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_time::Timer;
+
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<f32>,
+        stops: usize
+    }
+
+    impl MotorOutput for Recorder {
+        async fn write(&mut self, commands: &MotorCommands) {
+            self.writes.push(commands[0]);
+        }
+
+        async fn stop(&mut self) {
+            self.stops += 1;
+        }
+    }
+
+    #[test]
+    fn watchdog_stops_motors_when_commands_stall() {
+        let commands = Signal::<CriticalSectionRawMutex, MotorCommands>::new();
+        let mut output = Recorder::default();
+        let feed = async {
+            for value in [0.2, 0.4] {
+                commands.signal([value; MAX_MOTORS]);
+                Timer::after_millis(5).await;
+            }
+            Timer::after_millis(25).await;
+            commands.signal([0.6; MAX_MOTORS]);
+            Timer::after_millis(5).await;
+        };
+        run(select(output.run(&commands, Duration::from_millis(10)), feed));
+        assert_eq!(output.writes, [0.2, 0.4, 0.6]);
+        assert_eq!(output.stops, 2);
+    }
+
+    #[test]
+    fn timing_hits_protocol_frequency() {
+        let timing = Timing::new(125_000_000, EscProtocol::OneShot125.frequency());
+        assert_eq!(timing.divider, 1);
+        assert_eq!(timing.top, 62_499);
+        assert_eq!(timing.compare(125.0), 15_625);
+        let pwm = Timing::new(125_000_000, 50);
+        assert_eq!(125_000_000 / pwm.divider as u32 / (pwm.top as u32 + 1), 50);
+    }
+}
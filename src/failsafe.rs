@@ -0,0 +1,360 @@
+use crate::arming::Arming;
+use crate::errors::DroneError;
+use crate::rc::RcFrame;
+use embassy_time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FailsafeConfig {
+    /// Silence on the RC link after which it counts as lost.
+    pub rc_timeout: Duration,
+    /// Age of the last good IMU sample after which the attitude counts as lost,
+    /// catching a sensor or interrupt line that stopped without reporting errors.
+    pub imu_timeout: Duration,
+    /// Consecutive IMU errors after which the attitude counts as lost.
+    pub imu_error_limit: u8,
+    /// How long the last command is held, waiting for the fault to clear.
+    pub hold: Duration,
+    /// Throttle used for the self-levelling descent.
+    pub descend_throttle: f32,
+    /// Length of the descent before the motors are disarmed.
+    pub descend: Duration
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        FailsafeConfig {
+            rc_timeout: Duration::from_millis(200),
+            imu_timeout: Duration::from_millis(20),
+            imu_error_limit: 5,
+            hold: Duration::from_millis(1000),
+            descend_throttle: 0.35,
+            descend: Duration::from_secs(10)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum FailsafeCause {
+    RcLost,
+    ImuFault
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum FailsafeStage {
+    /// No fault, normal flight.
+    Idle,
+    /// Keep flying the last command; returns to `Idle` if the fault clears in time.
+    Hold,
+    /// Level out and descend at a fixed throttle. Latched until reset.
+    Descend,
+    /// Motors off, entered directly on an IMU fault. Latched until reset.
+    Disarm
+}
+
+/// What the flight loop should fly during the current stage.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub enum FailsafeAction {
+    Normal,
+    HoldLast,
+    /// Self-level with roll and pitch at zero and hold this throttle.
+    Descend { throttle: f32 },
+    Disarm
+}
+
+/// Staged response to a lost RC link or a failing IMU. Holding and self-levelling need a
+/// working attitude estimate, so an IMU fault disarms at once.
+#[derive(Debug, Copy, Clone)]
+pub struct Failsafe {
+    pub config: FailsafeConfig,
+    stage: FailsafeStage,
+    entered: Instant,
+    cause: Option<FailsafeCause>,
+    last_rc: Option<Instant>,
+    last_imu: Option<Instant>,
+    imu_errors: u8
+}
+
+impl Failsafe {
+    pub fn new(config: FailsafeConfig) -> Self {
+        Failsafe {
+            config,
+            stage: FailsafeStage::Idle,
+            entered: Instant::from_ticks(0),
+            cause: None,
+            last_rc: None,
+            last_imu: None,
+            imu_errors: 0
+        }
+    }
+
+    pub fn stage(&self) -> FailsafeStage {
+        self.stage
+    }
+
+    /// What triggered the current failsafe, if any.
+    pub fn cause(&self) -> Option<FailsafeCause> {
+        self.cause
+    }
+
+    /// Records a received RC frame. Frames flagged failsafe by the receiver do not count.
+    pub fn rc_frame(&mut self, at: Instant, frame: &RcFrame) {
+        if !frame.failsafe {
+            self.last_rc = Some(at);
+        }
+    }
+
+    /// Records a good IMU sample taken at `at`.
+    pub fn imu_ok(&mut self, at: Instant) {
+        self.last_imu = Some(at);
+        self.imu_errors = 0;
+    }
+
+    pub fn imu_error(&mut self, error: &DroneError) {
+        self.imu_errors = self.imu_errors.saturating_add(1);
+        defmt::warn!("IMU error {}, {} in a row", error, self.imu_errors);
+    }
+
+    /// Clears all stages and fault history, e.g. once disarmed on the ground.
+    pub fn reset(&mut self) {
+        self.stage = FailsafeStage::Idle;
+        self.cause = None;
+        self.imu_errors = 0;
+    }
+
+    fn fault(&self, now: Instant) -> Option<FailsafeCause> {
+        let imu_stale = match self.last_imu {
+            Some(last) => now.saturating_duration_since(last) >= self.config.imu_timeout,
+            None => true
+        };
+        if imu_stale || self.imu_errors >= self.config.imu_error_limit {
+            return Some(FailsafeCause::ImuFault)
+        }
+        match self.last_rc {
+            Some(last) if now.saturating_duration_since(last) < self.config.rc_timeout => None,
+            _ => Some(FailsafeCause::RcLost)
+        }
+    }
+
+    fn enter(&mut self, stage: FailsafeStage, now: Instant) {
+        defmt::warn!("failsafe {} -> {} ({})", self.stage, stage, self.cause);
+        self.stage = stage;
+        self.entered = now;
+    }
+
+    /// Advances the stages to `now` and returns what to fly.
+    pub fn update(&mut self, now: Instant) -> FailsafeAction {
+        let fault = self.fault(now);
+        let elapsed = now.saturating_duration_since(self.entered);
+        if fault == Some(FailsafeCause::ImuFault) && self.stage != FailsafeStage::Disarm {
+            self.cause = fault;
+            self.enter(FailsafeStage::Disarm, now);
+            return self.action()
+        }
+        match self.stage {
+            FailsafeStage::Idle => {
+                if fault.is_some() {
+                    self.cause = fault;
+                    self.enter(FailsafeStage::Hold, now);
+                }
+            }
+            FailsafeStage::Hold => {
+                if fault.is_none() {
+                    self.enter(FailsafeStage::Idle, now);
+                    self.cause = None;
+                } else if elapsed >= self.config.hold {
+                    self.enter(FailsafeStage::Descend, now);
+                }
+            }
+            FailsafeStage::Descend => {
+                if elapsed >= self.config.descend {
+                    self.enter(FailsafeStage::Disarm, now);
+                }
+            }
+            FailsafeStage::Disarm => {}
+        }
+        self.action()
+    }
+
+    pub fn action(&self) -> FailsafeAction {
+        match self.stage {
+            FailsafeStage::Idle => FailsafeAction::Normal,
+            FailsafeStage::Hold => FailsafeAction::HoldLast,
+            FailsafeStage::Descend => FailsafeAction::Descend { throttle: self.config.descend_throttle },
+            FailsafeStage::Disarm => FailsafeAction::Disarm
+        }
+    }
+
+    /// Mirrors the current stage onto the flight mode.
+    pub fn apply(&self, arming: &mut Arming) {
+        match self.stage {
+            FailsafeStage::Idle => arming.recover(),
+            FailsafeStage::Hold => arming.enter_failsafe(),
+            FailsafeStage::Descend => arming.land(),
+            FailsafeStage::Disarm => arming.disarm()
+        }
+    }
+}
+
+impl Default for Failsafe {
+    fn default() -> Self {
+        Failsafe::new(FailsafeConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arming::{ArmInputs, FlightMode, ImuCheck};
+    use crate::attitude::Euler;
+    use crate::mock::time_lock;
+    use crate::rc::RC_CHANNELS;
+    use embassy_time::MockDriver;
+
+    const TICK: Duration = Duration::from_millis(2);
+
+    const FRAME: RcFrame = RcFrame {
+        channels: [0.0; RC_CHANNELS],
+        count: 16,
+        failsafe: false,
+        frame_lost: false
+    };
+
+    fn armed() -> Arming {
+        let mut arming = Arming::default();
+        arming.set_imu_check(ImuCheck { verified: true, self_test_passed: true });
+        let inputs = ArmInputs {
+            attitude: Euler::default(),
+            throttle: 0.0,
+            rc_healthy: true,
+            battery_volts: 12.0
+        };
+        assert_eq!(arming.arm(&inputs), Ok(()));
+        arming
+    }
+
+    /// Runs the flight loop's failsafe bookkeeping on the mock clock for `duration`.
+    fn fly(failsafe: &mut Failsafe, duration: Duration, rc: bool, imu: bool) -> FailsafeAction {
+        let end = Instant::now() + duration;
+        let mut action = failsafe.action();
+        while Instant::now() < end {
+            MockDriver::get().advance(TICK);
+            let now = Instant::now();
+            if rc {
+                failsafe.rc_frame(now, &FRAME);
+            }
+            if imu {
+                failsafe.imu_ok(now);
+            }
+            action = failsafe.update(now);
+        }
+        action
+    }
+
+    #[test]
+    fn healthy_links_fly_normally() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        assert_eq!(fly(&mut failsafe, Duration::from_secs(5), true, true), FailsafeAction::Normal);
+        assert_eq!(failsafe.cause(), None);
+    }
+
+    #[test]
+    fn rc_loss_walks_through_stages() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        let config = failsafe.config;
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+
+        assert_eq!(fly(&mut failsafe, config.rc_timeout - TICK, false, true), FailsafeAction::Normal);
+        assert_eq!(fly(&mut failsafe, TICK, false, true), FailsafeAction::HoldLast);
+        assert_eq!(failsafe.cause(), Some(FailsafeCause::RcLost));
+
+        assert_eq!(fly(&mut failsafe, config.hold - TICK, false, true), FailsafeAction::HoldLast);
+        let descend = FailsafeAction::Descend { throttle: config.descend_throttle };
+        assert_eq!(fly(&mut failsafe, TICK, false, true), descend);
+
+        let mut arming = armed();
+        failsafe.apply(&mut arming);
+        assert_eq!(arming.mode(), FlightMode::Landing);
+
+        // Latched: the link coming back does not cancel the descent.
+        assert_eq!(fly(&mut failsafe, config.descend - TICK, true, true), descend);
+        assert_eq!(fly(&mut failsafe, TICK, true, true), FailsafeAction::Disarm);
+        assert_eq!(fly(&mut failsafe, Duration::from_secs(1), true, true), FailsafeAction::Disarm);
+
+        failsafe.apply(&mut arming);
+        assert_eq!(arming.mode(), FlightMode::Disarmed);
+        failsafe.reset();
+        assert_eq!(failsafe.stage(), FailsafeStage::Idle);
+    }
+
+    #[test]
+    fn rc_recovery_during_hold_returns_to_normal() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+        assert_eq!(fly(&mut failsafe, Duration::from_millis(500), false, true), FailsafeAction::HoldLast);
+        assert_eq!(fly(&mut failsafe, TICK, true, true), FailsafeAction::Normal);
+        assert_eq!(failsafe.cause(), None);
+    }
+
+    #[test]
+    fn receiver_failsafe_frames_count_as_loss() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+        let frame = RcFrame { failsafe: true, ..FRAME };
+        for _ in 0..200 {
+            MockDriver::get().advance(TICK);
+            failsafe.rc_frame(Instant::now(), &frame);
+            failsafe.imu_ok(Instant::now());
+            failsafe.update(Instant::now());
+        }
+        assert_eq!(failsafe.stage(), FailsafeStage::Hold);
+    }
+
+    #[test]
+    fn silent_imu_triggers_fault() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        let config = failsafe.config;
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+        assert_eq!(fly(&mut failsafe, config.imu_timeout - TICK, true, false), FailsafeAction::Normal);
+        assert_eq!(fly(&mut failsafe, TICK, true, false), FailsafeAction::Disarm);
+        assert_eq!(failsafe.cause(), Some(FailsafeCause::ImuFault));
+        // Latched: samples coming back do not re-enable the motors.
+        assert_eq!(fly(&mut failsafe, TICK, true, true), FailsafeAction::Disarm);
+    }
+
+    #[test]
+    fn repeated_imu_errors_trigger_fault() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+        let now = Instant::now();
+        for _ in 0..failsafe.config.imu_error_limit - 1 {
+            failsafe.imu_error(&DroneError::FifoOverflow);
+        }
+        assert_eq!(failsafe.update(now), FailsafeAction::Normal);
+        failsafe.imu_error(&DroneError::FifoOverflow);
+        assert_eq!(failsafe.update(now), FailsafeAction::Disarm);
+        assert_eq!(failsafe.cause(), Some(FailsafeCause::ImuFault));
+    }
+
+    #[test]
+    fn imu_fault_during_rc_hold_disarms() {
+        let _time = time_lock();
+        let mut failsafe = Failsafe::default();
+        fly(&mut failsafe, Duration::from_millis(100), true, true);
+        assert_eq!(fly(&mut failsafe, Duration::from_millis(300), false, true), FailsafeAction::HoldLast);
+        let mut arming = armed();
+        failsafe.apply(&mut arming);
+        assert_eq!(arming.mode(), FlightMode::Failsafe);
+
+        let imu_timeout = failsafe.config.imu_timeout;
+        assert_eq!(fly(&mut failsafe, imu_timeout, false, false), FailsafeAction::Disarm);
+        assert_eq!(failsafe.cause(), Some(FailsafeCause::ImuFault));
+        failsafe.apply(&mut arming);
+        assert_eq!(arming.mode(), FlightMode::Disarmed);
+    }
+}
@@ -0,0 +1,554 @@
+use crate::attitude::{unit, AttitudeEstimator, Quaternion};
+use crate::mpu6050::ScaledSample;
+use libm::{atan2f, sqrtf};
+
+/// Estimator that can additionally fuse a magnetometer for drift-free yaw.
+pub trait AhrsEstimator: AttitudeEstimator {
+    /// `mag` is in any unit, only its direction is used. Without it the update is IMU-only.
+    fn update_with_mag(&mut self, sample: &ScaledSample, mag: Option<[f32; 3]>, dt: f32);
+}
+
+/// Madgwick's gradient-descent orientation filter.
+#[derive(Debug, Copy, Clone)]
+pub struct Madgwick {
+    /// Gradient step, roughly `sqrt(3/4)` times the gyro measurement error in rad/s.
+    pub beta: f32,
+    q: Quaternion
+}
+
+impl Madgwick {
+    pub fn new(beta: f32) -> Self {
+        Madgwick {
+            beta,
+            q: Quaternion::IDENTITY
+        }
+    }
+
+    /// Objective function gradient for gravity only.
+    fn imu_gradient(&self, a: [f32; 3]) -> [f32; 4] {
+        let Quaternion { w: q0, x: q1, y: q2, z: q3 } = self.q;
+        let [ax, ay, az] = a;
+        let f = [
+            2.0 * (q1 * q3 - q0 * q2) - ax,
+            2.0 * (q0 * q1 + q2 * q3) - ay,
+            2.0 * (0.5 - q1 * q1 - q2 * q2) - az
+        ];
+        [
+            -2.0 * q2 * f[0] + 2.0 * q1 * f[1],
+            2.0 * q3 * f[0] + 2.0 * q0 * f[1] - 4.0 * q1 * f[2],
+            -2.0 * q0 * f[0] + 2.0 * q3 * f[1] - 4.0 * q2 * f[2],
+            2.0 * q1 * f[0] + 2.0 * q2 * f[1]
+        ]
+    }
+
+    /// Objective function gradient for the earth field `[bx, 0, bz]` measured as `m`.
+    fn mag_gradient(&self, m: [f32; 3]) -> [f32; 4] {
+        let Quaternion { w: q0, x: q1, y: q2, z: q3 } = self.q;
+        let [mx, my, mz] = m;
+        let h = self.q.rotate(m);
+        let bx = sqrtf(h[0] * h[0] + h[1] * h[1]);
+        let bz = h[2];
+        let f = [
+            2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - mx,
+            2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - my,
+            2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - mz
+        ];
+        [
+            -2.0 * bz * q2 * f[0] + (-2.0 * bx * q3 + 2.0 * bz * q1) * f[1] + 2.0 * bx * q2 * f[2],
+            2.0 * bz * q3 * f[0] + (2.0 * bx * q2 + 2.0 * bz * q0) * f[1]
+                + (2.0 * bx * q3 - 4.0 * bz * q1) * f[2],
+            (-4.0 * bx * q2 - 2.0 * bz * q0) * f[0] + (2.0 * bx * q1 + 2.0 * bz * q3) * f[1]
+                + (2.0 * bx * q0 - 4.0 * bz * q2) * f[2],
+            (-4.0 * bx * q3 + 2.0 * bz * q1) * f[0] + (-2.0 * bx * q0 + 2.0 * bz * q2) * f[1]
+                + 2.0 * bx * q1 * f[2]
+        ]
+    }
+}
+
+impl Default for Madgwick {
+    fn default() -> Self {
+        Madgwick::new(0.1)
+    }
+}
+
+impl AhrsEstimator for Madgwick {
+    fn update_with_mag(&mut self, sample: &ScaledSample, mag: Option<[f32; 3]>, dt: f32) {
+        let [gx, gy, gz] = sample.gyro;
+        let rate = self.q * Quaternion { w: 0.0, x: gx, y: gy, z: gz };
+        let mut q_dot = [0.5 * rate.w, 0.5 * rate.x, 0.5 * rate.y, 0.5 * rate.z];
+
+        if let Some(a) = unit(sample.accel) {
+            let mut gradient = self.imu_gradient(a);
+            if let Some(m) = mag.and_then(unit) {
+                let mag_gradient = self.mag_gradient(m);
+                for i in 0..4 {
+                    gradient[i] += mag_gradient[i];
+                }
+            }
+            let norm = sqrtf(gradient.iter().map(|s| s * s).sum());
+            if norm > f32::EPSILON {
+                for i in 0..4 {
+                    q_dot[i] -= self.beta * gradient[i] / norm;
+                }
+            }
+        }
+
+        self.q = Quaternion {
+            w: self.q.w + q_dot[0] * dt,
+            x: self.q.x + q_dot[1] * dt,
+            y: self.q.y + q_dot[2] * dt,
+            z: self.q.z + q_dot[3] * dt
+        }.normalize();
+    }
+}
+
+impl AttitudeEstimator for Madgwick {
+    fn update(&mut self, sample: &ScaledSample, dt: f32) {
+        self.update_with_mag(sample, None, dt);
+    }
+
+    fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+    }
+}
+
+type Mat<const R: usize, const C: usize> = [[f32; C]; R];
+
+fn mat_mul<const R: usize, const N: usize, const C: usize>(a: &Mat<R, N>, b: &Mat<N, C>) -> Mat<R, C> {
+    let mut out = [[0.0; C]; R];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..N).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn transpose<const R: usize, const C: usize>(a: &Mat<R, C>) -> Mat<C, R> {
+    let mut out = [[0.0; R]; C];
+    for (i, row) in a.iter().enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            out[j][i] = *value;
+        }
+    }
+    out
+}
+
+fn invert3(m: &Mat<3, 3>) -> Option<Mat<3, 3>> {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adj = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)]
+    ];
+    let det = m[0][0] * adj[0][0] + m[0][1] * adj[1][0] + m[0][2] * adj[2][0];
+    if det == 0.0 || !det.is_finite() {
+        return None
+    }
+    Some(adj.map(|row| row.map(|v| v / det)))
+}
+
+/// Quaternion EKF: the gyro drives the prediction, the gravity direction and the optional
+/// magnetic heading are sequential measurement updates.
+#[derive(Debug, Copy, Clone)]
+pub struct Ekf {
+    /// Gyro noise density in rad/s/√Hz.
+    pub gyro_noise: f32,
+    /// Noise of the normalized accelerometer direction.
+    pub accel_noise: f32,
+    /// Noise of the magnetometer heading in radians.
+    pub mag_noise: f32,
+    q: Quaternion,
+    p: Mat<4, 4>
+}
+
+impl Ekf {
+    const INITIAL_VARIANCE: f32 = 0.1;
+
+    pub fn new(gyro_noise: f32, accel_noise: f32, mag_noise: f32) -> Self {
+        Ekf {
+            gyro_noise,
+            accel_noise,
+            mag_noise,
+            q: Quaternion::IDENTITY,
+            p: Self::initial_covariance()
+        }
+    }
+
+    fn initial_covariance() -> Mat<4, 4> {
+        let mut p = [[0.0; 4]; 4];
+        for (i, row) in p.iter_mut().enumerate() {
+            row[i] = Self::INITIAL_VARIANCE;
+        }
+        p
+    }
+
+    /// State covariance in `w, x, y, z` order.
+    pub fn covariance(&self) -> Mat<4, 4> {
+        self.p
+    }
+
+    /// Trace of the covariance, a scalar confidence for telemetry and arming checks.
+    pub fn uncertainty(&self) -> f32 {
+        (0..4).map(|i| self.p[i][i]).sum()
+    }
+
+    fn state(&self) -> [f32; 4] {
+        [self.q.w, self.q.x, self.q.y, self.q.z]
+    }
+
+    fn predict(&mut self, gyro: [f32; 3], dt: f32) {
+        let [wx, wy, wz] = gyro.map(|w| 0.5 * dt * w);
+        let f = [
+            [1.0, -wx, -wy, -wz],
+            [wx, 1.0, wz, -wy],
+            [wy, -wz, 1.0, wx],
+            [wz, wy, -wx, 1.0]
+        ];
+        let q = mat_mul(&f, &transpose(&[self.state()]));
+        self.q = Quaternion { w: q[0][0], x: q[1][0], y: q[2][0], z: q[3][0] }.normalize();
+
+        let Quaternion { w, x, y, z } = self.q;
+        let xi = [
+            [-x, -y, -z],
+            [w, -z, y],
+            [z, w, -x],
+            [-y, x, w]
+        ];
+        let scale = 0.25 * self.gyro_noise * self.gyro_noise * dt;
+        let q_noise = mat_mul(&xi, &transpose(&xi));
+        let fp = mat_mul(&f, &self.p);
+        let mut p = mat_mul(&fp, &transpose(&f));
+        for i in 0..4 {
+            for j in 0..4 {
+                p[i][j] += scale * q_noise[i][j];
+            }
+        }
+        self.p = p;
+    }
+
+    fn correct(&mut self, z: [f32; 3], h: [f32; 3], jacobian: Mat<3, 4>, noise: f32) {
+        let pht = mat_mul(&self.p, &transpose(&jacobian));
+        let mut s = mat_mul(&jacobian, &pht);
+        for (i, row) in s.iter_mut().enumerate() {
+            row[i] += noise * noise;
+        }
+        let Some(s_inv) = invert3(&s) else {
+            return
+        };
+        let k = mat_mul(&pht, &s_inv);
+        self.apply_gain(&k, [z[0] - h[0], z[1] - h[1], z[2] - h[2]], &jacobian);
+    }
+
+    /// Scalar yaw update, so the magnetometer never tilts the roll/pitch estimate.
+    fn correct_heading(&mut self, innovation: f32, noise: f32) {
+        let Quaternion { w, x, y, z } = self.q;
+        let num = 2.0 * (w * z + x * y);
+        let den = 1.0 - 2.0 * (y * y + z * z);
+        let norm = num * num + den * den;
+        if norm < f32::EPSILON {
+            return
+        }
+        let d_num = [2.0 * z, 2.0 * y, 2.0 * x, 2.0 * w];
+        let d_den = [0.0, 0.0, -4.0 * y, -4.0 * z];
+        let jacobian = [[0, 1, 2, 3].map(|i| (den * d_num[i] - num * d_den[i]) / norm)];
+        let pht = mat_mul(&self.p, &transpose(&jacobian));
+        let s = mat_mul(&jacobian, &pht)[0][0] + noise * noise;
+        let k = pht.map(|row| [row[0] / s]);
+        self.apply_gain(&k, [innovation], &jacobian);
+    }
+
+    fn apply_gain<const M: usize>(&mut self, k: &Mat<4, M>, innovation: [f32; M], jacobian: &Mat<M, 4>) {
+        let mut x = self.state();
+        for (i, value) in x.iter_mut().enumerate() {
+            *value += (0..M).map(|j| k[i][j] * innovation[j]).sum::<f32>();
+        }
+        self.q = Quaternion { w: x[0], x: x[1], y: x[2], z: x[3] }.normalize();
+
+        let kh = mat_mul(k, jacobian);
+        let mut i_kh = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                i_kh[i][j] = if i == j { 1.0 } else { 0.0 } - kh[i][j];
+            }
+        }
+        self.p = mat_mul(&i_kh, &self.p);
+    }
+
+    /// Jacobian of [`Quaternion::gravity`].
+    fn gravity_jacobian(&self) -> Mat<3, 4> {
+        let Quaternion { w, x, y, z } = self.q;
+        [
+            [-2.0 * y, 2.0 * z, -2.0 * w, 2.0 * x],
+            [2.0 * x, 2.0 * w, 2.0 * z, 2.0 * y],
+            [2.0 * w, -2.0 * x, -2.0 * y, 2.0 * z]
+        ]
+    }
+}
+
+impl Default for Ekf {
+    fn default() -> Self {
+        Ekf::new(0.02, 0.05, 0.05)
+    }
+}
+
+impl AhrsEstimator for Ekf {
+    fn update_with_mag(&mut self, sample: &ScaledSample, mag: Option<[f32; 3]>, dt: f32) {
+        self.predict(sample.gyro, dt);
+
+        if let Some(a) = unit(sample.accel) {
+            let h = self.q.gravity();
+            let jacobian = self.gravity_jacobian();
+            self.correct(a, h, jacobian, self.accel_noise);
+        }
+
+        if let Some(m) = mag.and_then(unit) {
+            // Heading of the measured field in the estimated earth frame; magnetic north is +X.
+            let earth = self.q.rotate(m);
+            if earth[0] * earth[0] + earth[1] * earth[1] > f32::EPSILON {
+                self.correct_heading(-atan2f(earth[1], earth[0]), self.mag_noise);
+            }
+        }
+    }
+}
+
+impl AttitudeEstimator for Ekf {
+    fn update(&mut self, sample: &ScaledSample, dt: f32) {
+        self.update_with_mag(sample, None, dt);
+    }
+
+    fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+        self.p = Self::initial_covariance();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attitude::Euler;
+    use crate::mpu6050::{AccelRange, GyroRange, Sample};
+
+    /// Sample period of the logs below.
+    const LOG_DT: f32 = 0.01;
+
+    /// Board resting on the bench at 10° roll and -5° pitch; accel then gyro counts at ±8 g, ±2000 °/s.
+    const BENCH_LOG: [[i16; 6]; 32] = [
+        [354, 714, 4016, -1, -3, -1],
+        [368, 713, 4029, 1, 1, 1],
+        [340, 717, 4023, 1, -5, -5],
+        [348, 704, 4021, 0, 2, -2],
+        [360, 712, 4012, 5, 2, 4],
+        [351, 701, 4015, 0, 2, 1],
+        [353, 699, 4013, 4, -2, 1],
+        [361, 694, 4019, 4, -6, -1],
+        [356, 700, 4023, 0, -4, 2],
+        [364, 718, 4033, 1, 0, -4],
+        [363, 702, 4014, -4, -3, -2],
+        [370, 688, 4004, 1, 4, 2],
+        [338, 683, 4022, -2, -3, 3],
+        [368, 710, 4021, 1, 5, 2],
+        [362, 714, 4003, 4, 3, 2],
+        [337, 702, 4027, -5, -1, 3],
+        [344, 725, 4024, 0, 1, 2],
+        [358, 720, 4012, -1, 3, 0],
+        [348, 718, 4033, -1, -4, 0],
+        [355, 706, 4032, -3, 4, -4],
+        [349, 715, 4030, 3, 1, 0],
+        [359, 714, 4017, 1, 2, 0],
+        [365, 714, 4039, 1, -1, -1],
+        [357, 718, 4015, 1, 6, -8],
+        [346, 711, 4022, 1, -1, 2],
+        [360, 703, 4043, 1, -2, 0],
+        [355, 708, 3991, -1, 3, -4],
+        [356, 718, 4027, 4, -5, -1],
+        [354, 715, 4029, -8, 3, -4],
+        [364, 694, 4020, 4, 0, 1],
+        [365, 710, 4018, 5, 3, -1],
+        [384, 697, 4028, -1, 0, 2]
+    ];
+
+    /// Level board turned one second at 90 °/s nose left; same format as [`BENCH_LOG`].
+    const YAW_TURN_LOG: [[i16; 6]; 100] = [
+        [2, 6, 4081, -5, 2, 1473],
+        [-10, -15, 4109, 2, 4, 1473],
+        [0, -11, 4104, 5, -3, 1481],
+        [10, -2, 4076, 4, 0, 1474],
+        [4, 4, 4111, -3, 3, 1480],
+        [15, -2, 4089, 3, 0, 1476],
+        [14, -3, 4073, -1, -6, 1478],
+        [3, -6, 4096, 2, 0, 1480],
+        [-1, 10, 4111, 5, -2, 1479],
+        [-19, -11, 4076, 3, -4, 1476],
+        [-2, 0, 4090, 1, 5, 1476],
+        [5, 10, 4094, -4, -2, 1479],
+        [-16, -6, 4106, 2, 0, 1478],
+        [2, -12, 4080, -2, 3, 1474],
+        [-9, -8, 4081, 0, -4, 1477],
+        [-24, 3, 4090, -6, 2, 1475],
+        [-22, -9, 4099, -1, 2, 1478],
+        [7, 3, 4109, 2, 1, 1470],
+        [9, 13, 4093, -1, 6, 1471],
+        [5, 24, 4087, 2, 6, 1476],
+        [6, 9, 4087, 0, 1, 1478],
+        [0, -2, 4086, -1, 3, 1476],
+        [-9, -8, 4123, 3, 2, 1468],
+        [6, 5, 4113, 1, 0, 1478],
+        [-19, 10, 4099, -2, 4, 1481],
+        [-14, -7, 4099, 1, -1, 1473],
+        [21, 10, 4084, -4, 5, 1479],
+        [18, 8, 4087, 1, -6, 1474],
+        [-1, 5, 4089, 0, 1, 1477],
+        [6, 2, 4093, 2, 0, 1474],
+        [-6, 0, 4095, 0, 0, 1477],
+        [-1, -13, 4100, 3, 1, 1475],
+        [4, -10, 4077, 0, -3, 1478],
+        [-11, -26, 4086, 5, -1, 1472],
+        [-8, 5, 4101, 1, 4, 1478],
+        [0, 6, 4113, 3, 3, 1473],
+        [-1, 7, 4093, 3, 2, 1479],
+        [-2, 25, 4108, -1, 0, 1484],
+        [-3, 9, 4106, 0, -4, 1477],
+        [4, 11, 4104, 0, 3, 1478],
+        [2, 1, 4094, 2, -3, 1474],
+        [0, -15, 4092, -6, -2, 1478],
+        [6, -1, 4094, -4, 5, 1478],
+        [11, -9, 4094, -5, 2, 1479],
+        [-19, -1, 4102, -5, -5, 1473],
+        [-6, -14, 4096, 1, 2, 1478],
+        [15, 12, 4083, -2, -3, 1473],
+        [-1, 0, 4101, -5, -4, 1476],
+        [-2, -3, 4095, -2, 2, 1477],
+        [-1, -7, 4094, -8, -3, 1476],
+        [-15, 2, 4097, -4, -1, 1475],
+        [5, 6, 4096, -3, 0, 1476],
+        [7, 3, 4089, -4, -1, 1474],
+        [-11, -1, 4091, 0, 2, 1475],
+        [23, -3, 4107, 0, 3, 1469],
+        [-8, 2, 4102, 7, 1, 1480],
+        [8, 9, 4101, 0, 2, 1473],
+        [12, -10, 4098, 6, -1, 1476],
+        [12, 0, 4088, 1, 2, 1478],
+        [-8, 18, 4113, 0, 1, 1475],
+        [14, -7, 4103, -1, -2, 1478],
+        [13, 0, 4089, 2, 0, 1477],
+        [15, 11, 4091, 7, 0, 1478],
+        [-6, 0, 4079, 5, 4, 1472],
+        [-15, -16, 4108, -1, 0, 1475],
+        [-1, -11, 4096, -4, 0, 1477],
+        [5, -2, 4087, 0, -1, 1481],
+        [8, -1, 4091, -2, -3, 1475],
+        [3, 5, 4102, 6, -2, 1476],
+        [28, -19, 4091, 1, 0, 1477],
+        [-2, 4, 4097, 2, -6, 1473],
+        [0, -10, 4086, 2, -2, 1478],
+        [7, 3, 4101, 0, -4, 1476],
+        [5, -5, 4095, 2, -3, 1478],
+        [19, -6, 4097, 0, 5, 1477],
+        [9, -7, 4096, 0, -5, 1480],
+        [9, -17, 4103, 0, 1, 1477],
+        [-15, -2, 4111, -2, -3, 1472],
+        [-12, 3, 4113, 1, 1, 1483],
+        [-5, -7, 4101, 2, -3, 1472],
+        [3, 2, 4083, -1, -2, 1477],
+        [-1, -1, 4092, 3, 4, 1475],
+        [8, -8, 4097, 2, 5, 1475],
+        [-1, 2, 4081, 0, -2, 1477],
+        [-11, -20, 4096, 1, -2, 1479],
+        [-3, -6, 4101, -5, -2, 1476],
+        [8, -2, 4099, -2, 1, 1481],
+        [-7, 24, 4090, 0, 1, 1479],
+        [-12, -21, 4102, 2, 2, 1484],
+        [2, 3, 4105, 1, 5, 1472],
+        [-4, -34, 4104, -1, 3, 1482],
+        [0, -3, 4091, -3, -2, 1478],
+        [0, 1, 4094, 3, 1, 1476],
+        [7, -2, 4084, 4, 1, 1473],
+        [11, 3, 4080, 5, 1, 1479],
+        [2, -1, 4081, 3, 0, 1475],
+        [4, 1, 4103, -1, 0, 1470],
+        [-4, 7, 4109, -1, 0, 1481],
+        [-3, 7, 4113, 0, 4, 1474],
+        [2, -1, 4097, 3, 7, 1474]
+    ];
+
+    fn scaled(row: &[i16; 6]) -> ScaledSample {
+        let sample = Sample {
+            accel: [row[0], row[1], row[2]],
+            temp: 0,
+            gyro: [row[3], row[4], row[5]]
+        };
+        sample.scale(AccelRange::G8, GyroRange::D2000)
+    }
+
+    fn replay(estimator: &mut impl AttitudeEstimator, log: &[[i16; 6]], passes: usize) -> Euler {
+        for _ in 0..passes {
+            for row in log {
+                estimator.update(&scaled(row), LOG_DT);
+            }
+        }
+        estimator.euler()
+    }
+
+    fn assert_degrees(actual: f32, expected: f32, tolerance: f32) {
+        let actual = actual.to_degrees();
+        assert!((actual - expected).abs() <= tolerance, "{actual}° differs from {expected}° by more than {tolerance}°");
+    }
+
+    #[test]
+    fn bench_log_levels_madgwick() {
+        let euler = replay(&mut Madgwick::default(), &BENCH_LOG, 100);
+        assert_degrees(euler.roll, 10.0, 1.0);
+        assert_degrees(euler.pitch, -5.0, 1.0);
+    }
+
+    #[test]
+    fn bench_log_levels_ekf() {
+        let mut ekf = Ekf::default();
+        let initial = ekf.uncertainty();
+        let euler = replay(&mut ekf, &BENCH_LOG, 100);
+        assert_degrees(euler.roll, 10.0, 1.0);
+        assert_degrees(euler.pitch, -5.0, 1.0);
+        assert!(ekf.uncertainty() < initial);
+    }
+
+    #[test]
+    fn yaw_turn_log_integrates_heading() {
+        let madgwick = replay(&mut Madgwick::default(), &YAW_TURN_LOG, 1);
+        let ekf = replay(&mut Ekf::default(), &YAW_TURN_LOG, 1);
+        for euler in [madgwick, ekf] {
+            assert_degrees(euler.yaw, 90.0, 2.0);
+            assert_degrees(euler.roll, 0.0, 1.0);
+            assert_degrees(euler.pitch, 0.0, 1.0);
+        }
+    }
+
+    #[test]
+    fn magnetometer_corrects_heading() {
+        // Level board pointing 1 rad left of magnetic north, field dipping downwards.
+        let heading = Quaternion::from_euler(Euler { roll: 0.0, pitch: 0.0, yaw: 1.0 });
+        let mag = heading.conjugate().rotate([0.4, 0.0, -0.3]);
+        let level = ScaledSample {
+            accel: [0.0, 0.0, crate::mpu6050::STANDARD_GRAVITY],
+            temp: 25.0,
+            gyro: [0.0; 3]
+        };
+        let mut madgwick = Madgwick::default();
+        let mut ekf = Ekf::default();
+        for _ in 0..3000 {
+            madgwick.update_with_mag(&level, Some(mag), LOG_DT);
+            ekf.update_with_mag(&level, Some(mag), LOG_DT);
+        }
+        assert_degrees(madgwick.euler().yaw, 1f32.to_degrees(), 1.0);
+        assert_degrees(ekf.euler().yaw, 1f32.to_degrees(), 1.0);
+        assert_degrees(ekf.euler().roll, 0.0, 0.5);
+    }
+}
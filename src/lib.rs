@@ -0,0 +1,18 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod mpu6050;
+pub mod errors;
+pub mod bus;
+pub mod attitude;
+pub mod fusion;
+pub mod control;
+pub mod mixer;
+pub mod esc;
+pub mod dshot;
+pub mod rc;
+pub mod arming;
+pub mod failsafe;
+pub mod timing;
+
+#[cfg(test)]
+mod mock;
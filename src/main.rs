@@ -1,32 +1,394 @@
 #![no_std]
 #![no_main]
-extern crate alloc;
-
-mod mpu6050;
-mod errors;
 
 pub use panic_probe;
 pub use defmt_rtt;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::Spawner;
+use embassy_rp::adc::{self, Adc};
 use embassy_rp::bind_interrupts;
-use embassy_rp::i2c::{Config, I2c, InterruptHandler};
-use embassy_rp::peripherals::I2C1;
-use crate::mpu6050::Mpu6050;
-use embedded_alloc::LlffHeap as Heap;
+use embassy_rp::gpio::{Input, Level, OutputOpenDrain, Pull};
+use embassy_rp::i2c::{self, Async, I2c};
+use embassy_rp::peripherals::{I2C1, PIN_14, PIN_15, UART0};
+use embassy_rp::pwm::{self, Pwm};
+use embassy_rp::uart::{BufferedInterruptHandler, BufferedUartRx};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use embedded_io_async::Read;
+use drone::arming::{Arming, ArmInputs, FlightMode, ImuCheck};
+use drone::attitude::{AttitudeEstimator, Estimator, Euler};
+use drone::bus::BusPolicy;
+use drone::control::{wrap_angle, AttitudeController, Setpoint};
+use drone::errors::Result;
+use drone::esc::{Esc, EscProtocol, MotorCommands, MotorOutput};
+use drone::failsafe::{Failsafe, FailsafeAction, FailsafeStage};
+use drone::mixer::{Frame, Mixer};
+use drone::mpu6050::{AccelRange, Address, GyroRange, Mpu6050, ScaledSample};
+use drone::rc::{Crsf, LinkStats, RcFrame, RcParser};
+use drone::timing::{LoopStats, LoopTimer};
+use static_cell::StaticCell;
+
+/// Rate of the estimator/controller loop and of the IMU sample clock.
+const LOOP_HZ: u32 = 1000;
+const TELEMETRY_HZ: u32 = 10;
+/// Motors stop if the flight loop stalls for this long.
+const OUTPUT_TIMEOUT: Duration = Duration::from_millis(20);
+/// IMU bus clock, also used to scale transaction timeouts.
+const I2C_FREQUENCY: u32 = 400_000;
+/// Consecutive read errors after which the IMU is reset.
+const IMU_RESET_ERRORS: u8 = 10;
+/// Longest gap between IMU samples integrated as one step, e.g. after a reset.
+const MAX_IMU_DT: f32 = 0.01;
+
+/// AETR channel order, arm switch on AUX1.
+const ROLL_CHANNEL: usize = 0;
+const PITCH_CHANNEL: usize = 1;
+const THROTTLE_CHANNEL: usize = 2;
+const YAW_CHANNEL: usize = 3;
+const ARM_CHANNEL: usize = 4;
+/// Full stick in angle mode, radians.
+const MAX_ANGLE: f32 = 0.5;
+/// Full yaw stick, rad/s.
+const MAX_YAW_RATE: f32 = 3.5;
+
+/// 12 bit ADC against 3.3 V behind a 10k/1k divider.
+const BATTERY_VOLTS_PER_COUNT: f32 = 3.3 / 4096.0 * 11.0;
+
+type ImuI2c = I2c<'static, I2C1, Async>;
+type SharedI2c = Mutex<NoopRawMutex, ImuI2c>;
+type ImuBus = I2cDevice<'static, NoopRawMutex, ImuI2c>;
+type ImuSda = PIN_14;
+type ImuScl = PIN_15;
 
-#[global_allocator]
-static HEAP: Heap = Heap::empty();
+static I2C_BUS: StaticCell<SharedI2c> = StaticCell::new();
+static RC_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
+
+static IMU_CHECK: Signal<CriticalSectionRawMutex, ImuCheck> = Signal::new();
+static IMU_SAMPLES: Channel<CriticalSectionRawMutex, ImuSample, 8> = Channel::new();
+static RC_FRAMES: Signal<CriticalSectionRawMutex, (RcFrame, LinkStats)> = Signal::new();
+static BATTERY: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+static MOTORS: Signal<CriticalSectionRawMutex, MotorCommands> = Signal::new();
+static TELEMETRY: Channel<CriticalSectionRawMutex, Telemetry, 4> = Channel::new();
 
 bind_interrupts!(struct Irqs {
-    I2C1_IRQ => InterruptHandler<I2C1>;
+    I2C1_IRQ => i2c::InterruptHandler<I2C1>;
+    UART0_IRQ => BufferedInterruptHandler<UART0>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
 });
 
+/// One IMU read, stamped when the data-ready wait returned.
+struct ImuSample {
+    at: Instant,
+    sample: Result<ScaledSample>
+}
+
+#[derive(Debug, Copy, Clone, defmt::Format)]
+struct Telemetry {
+    mode: FlightMode,
+    failsafe: FailsafeStage,
+    attitude: Euler,
+    throttle: f32,
+    battery_volts: f32,
+    link: LinkStats,
+    timing: LoopStats
+}
+
+async fn setup_imu(imu: &mut Mpu6050<ImuBus>) -> Result<ImuCheck> {
+    imu.init_with_gyro_accel_range(Duration::from_millis(100), AccelRange::G8, GyroRange::D2000).await?;
+    imu.set_sample_rate(LOOP_HZ).await?;
+    let check = drone::arming::check_imu(imu).await?;
+    imu.enable_data_ready_interrupt().await?;
+    Ok(check)
+}
+
+/// 400 kHz fast mode, the fastest the MPU6050 supports.
+fn i2c_config() -> i2c::Config {
+    let mut config = i2c::Config::default();
+    config.frequency = I2C_FREQUENCY;
+    config
+}
+
+/// Clocks a slave holding SDA low off the IMU bus, then rebuilds the I2C driver on the same pins.
+async fn recover_i2c_bus(bus: &'static SharedI2c) -> Result<()> {
+    let mut i2c = bus.lock().await;
+    // SAFETY: the driver behind the lock owns these peripherals. It is not used while the lock is
+    // held, the GPIO drivers are dropped before the pins go back to I2C, and it is replaced below.
+    let recovered = {
+        let mut scl = OutputOpenDrain::new(unsafe { ImuScl::steal() }, Level::High);
+        let mut sda = OutputOpenDrain::new(unsafe { ImuSda::steal() }, Level::High);
+        drone::bus::recover_bus(&mut scl, &mut sda, Duration::from_micros(5)).await
+    };
+    let (i2c1, scl, sda) = unsafe { (I2C1::steal(), ImuScl::steal(), ImuSda::steal()) };
+    *i2c = I2c::new_async(i2c1, scl, sda, Irqs, i2c_config());
+    recovered
+}
+
+/// Resets an IMU that keeps failing, freeing the bus first if the reset cannot reach it.
+async fn recover_imu(imu: &mut Mpu6050<ImuBus>, bus: &'static SharedI2c) -> Result<()> {
+    let Err(error) = imu.reset().await else {
+        return Ok(())
+    };
+    defmt::warn!("IMU reset failed: {}, recovering bus", error);
+    recover_i2c_bus(bus).await?;
+    imu.reset().await
+}
+
+/// Retries the setup until the IMU answers, then publishes its preflight check.
+async fn start_imu(imu: &mut Mpu6050<ImuBus>) {
+    let check = loop {
+        match setup_imu(imu).await {
+            Ok(check) => break check,
+            Err(error) => {
+                defmt::error!("IMU setup failed: {}", error);
+                Timer::after_millis(500).await;
+            }
+        }
+    };
+    defmt::info!("IMU check: {}", check);
+    IMU_CHECK.signal(check);
+}
+
+/// Queues every IMU sample, or the error reading it, as soon as the chip raises data ready.
+#[embassy_executor::task]
+async fn imu_task(mut imu: Mpu6050<ImuBus>, mut int_pin: Input<'static>, bus: &'static SharedI2c) {
+    start_imu(&mut imu).await;
+
+    let mut errors = 0u8;
+    loop {
+        let sample = imu.wait_for_data_ready(&mut int_pin).await.map(|raw| {
+            let mut sample = raw.scale(imu.accel_range(), imu.gyro_range());
+            imu.calibration().apply(&mut sample);
+            sample
+        });
+        errors = if sample.is_ok() { 0 } else { errors.saturating_add(1) };
+        if IMU_SAMPLES.try_send(ImuSample { at: Instant::now(), sample }).is_err() {
+            defmt::warn!("IMU sample queue full, dropping sample");
+        }
+
+        if errors >= IMU_RESET_ERRORS {
+            errors = 0;
+            if let Err(error) = recover_imu(&mut imu, bus).await {
+                defmt::error!("IMU recovery failed: {}", error);
+                start_imu(&mut imu).await;
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn rc_task(mut rx: BufferedUartRx<'static, UART0>) {
+    let mut parser = Crsf::new();
+    let mut buffer = [0u8; 64];
+    loop {
+        match rx.read(&mut buffer).await {
+            Ok(len) => {
+                for &byte in &buffer[..len] {
+                    if let Some(frame) = parser.push(byte) {
+                        RC_FRAMES.signal((frame, parser.stats()));
+                    }
+                }
+            }
+            Err(error) => defmt::warn!("RC UART error: {}", error)
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn battery_task(mut adc: Adc<'static, adc::Async>, mut channel: adc::Channel<'static>) {
+    let mut ticker = Ticker::every(Duration::from_millis(100));
+    loop {
+        match adc.read(&mut channel).await {
+            Ok(raw) => BATTERY.signal(raw as f32 * BATTERY_VOLTS_PER_COUNT),
+            Err(error) => defmt::warn!("battery ADC error: {}", error)
+        }
+        ticker.next().await;
+    }
+}
+
+#[embassy_executor::task]
+async fn output_task(mut esc: Esc<'static, 2>) {
+    esc.run(&MOTORS, OUTPUT_TIMEOUT).await
+}
+
+#[embassy_executor::task]
+async fn telemetry_task() {
+    loop {
+        let telemetry = TELEMETRY.receive().await;
+        defmt::info!("{}", telemetry);
+    }
+}
+
+/// Estimator, arming, failsafe, controller and mixer, once per tick.
+#[embassy_executor::task]
+async fn flight_task(loop_hz: u32) {
+    let period = Duration::from_hz(loop_hz as u64);
+    let dt = 1.0 / loop_hz as f32;
+    let telemetry_every = (loop_hz / TELEMETRY_HZ).max(1);
+    let mut ticker = Ticker::every(period);
+    let mut timer = LoopTimer::new(period);
+
+    let mut estimator = Estimator::default();
+    let mut controller = AttitudeController::default();
+    let mixer = Mixer::new(Frame::QuadX);
+    let mut arming = Arming::default();
+    let mut failsafe = Failsafe::default();
+
+    let mut rc: Option<(RcFrame, Instant)> = None;
+    let mut last_sample: Option<Instant> = None;
+    let mut link = LinkStats::default();
+    let mut gyro = [0.0; 3];
+    let mut battery_volts = 0.0;
+    // Start as if the switch were on so a switch left on at boot has to be cycled first.
+    let mut arm_switch_was_on = true;
+    let mut yaw_target = 0.0;
+    let mut held = (0.0, Setpoint::Rate([0.0; 3]));
+    let mut iteration = 0u32;
+
+    loop {
+        ticker.next().await;
+        let now = Instant::now();
+        timer.start(now);
+
+        if let Some(check) = IMU_CHECK.try_take() {
+            arming.set_imu_check(check);
+        }
+        if let Some(volts) = BATTERY.try_take() {
+            battery_volts = volts;
+        }
+        if let Some((frame, stats)) = RC_FRAMES.try_take() {
+            failsafe.rc_frame(now, &frame);
+            rc = Some((frame, now));
+            link = stats;
+        }
+        while let Ok(ImuSample { at, sample }) = IMU_SAMPLES.try_receive() {
+            match sample {
+                Ok(sample) => {
+                    let imu_dt = last_sample
+                        .replace(at)
+                        .map_or(dt, |last| at.saturating_duration_since(last).as_micros() as f32 * 1e-6);
+                    failsafe.imu_ok(at);
+                    estimator.update(&sample, imu_dt.min(MAX_IMU_DT));
+                    gyro = sample.gyro;
+                }
+                Err(error) => failsafe.imu_error(&error)
+            }
+        }
+        let attitude = estimator.euler();
+
+        let frame = rc.map(|(frame, _)| frame);
+        let channel = |index| frame.map_or(0.0, |frame| frame.channel(index));
+        let throttle = (channel(THROTTLE_CHANNEL) + 1.0) * 0.5;
+        let rc_healthy = rc.is_some_and(|(frame, at)| {
+            !frame.failsafe && now.saturating_duration_since(at) < failsafe.config.rc_timeout
+        });
+
+        let arm_switch_on = channel(ARM_CHANNEL) > 0.5;
+        if arm_switch_on && !arm_switch_was_on && arming.mode() == FlightMode::Disarmed {
+            let inputs = ArmInputs { attitude, throttle, rc_healthy, battery_volts };
+            if arming.arm(&inputs).is_ok() {
+                yaw_target = attitude.yaw;
+            }
+        } else if !arm_switch_on && arming.is_armed() {
+            arming.disarm();
+        }
+        arm_switch_was_on = arm_switch_on;
+
+        let action = if arming.is_armed() {
+            let action = failsafe.update(now);
+            failsafe.apply(&mut arming);
+            action
+        } else {
+            failsafe.reset();
+            FailsafeAction::Normal
+        };
+
+        let command = match action {
+            FailsafeAction::Normal => {
+                yaw_target = wrap_angle(yaw_target - channel(YAW_CHANNEL) * MAX_YAW_RATE * dt);
+                let setpoint = Setpoint::Angle(Euler {
+                    roll: channel(ROLL_CHANNEL) * MAX_ANGLE,
+                    pitch: channel(PITCH_CHANNEL) * MAX_ANGLE,
+                    yaw: yaw_target
+                });
+                held = (throttle, setpoint);
+                held
+            }
+            FailsafeAction::HoldLast => held,
+            FailsafeAction::Descend { throttle } => {
+                let level = Euler { roll: 0.0, pitch: 0.0, yaw: attitude.yaw };
+                (throttle, Setpoint::Angle(level))
+            }
+            FailsafeAction::Disarm => (0.0, Setpoint::Rate([0.0; 3]))
+        };
+
+        let output = if arming.is_armed() {
+            let torque = controller.update(command.1, &attitude, gyro, dt);
+            mixer.mix(command.0, torque)
+        } else {
+            controller.reset();
+            yaw_target = attitude.yaw;
+            mixer.stop()
+        };
+        MOTORS.signal(output.motors);
+
+        iteration = iteration.wrapping_add(1);
+        if iteration.is_multiple_of(telemetry_every) {
+            let _ = TELEMETRY.try_send(Telemetry {
+                mode: arming.mode(),
+                failsafe: failsafe.stage(),
+                attitude,
+                throttle: command.0,
+                battery_volts,
+                link,
+                timing: timer.stats()
+            });
+        }
+        timer.finish(Instant::now());
+    }
+}
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let peripheral = embassy_rp::init(Default::default());
-    let sda = peripheral.PIN_14;
-    let scl = peripheral.PIN_15;
-    let mut i2c = I2c::new_async(peripheral.I2C1, scl, sda, Irqs, Config::default());
+    // Keep in step with `ImuSda` and `ImuScl`, which bus recovery steals.
+    let sda: ImuSda = peripheral.PIN_14;
+    let scl: ImuScl = peripheral.PIN_15;
+    let i2c = I2c::new_async(peripheral.I2C1, scl, sda, Irqs, i2c_config());
+    let i2c_bus = I2C_BUS.init(Mutex::new(i2c));
+
+    let mut imu = Mpu6050::new(I2cDevice::new(i2c_bus), Address::Ad0Low);
+    imu.set_bus_policy(BusPolicy {
+        frequency: I2C_FREQUENCY,
+        ..BusPolicy::default()
+    });
+    let int_pin = Input::new(peripheral.PIN_16, Pull::None);
+
+    let rc_rx = BufferedUartRx::new(
+        peripheral.UART0,
+        Irqs,
+        peripheral.PIN_1,
+        RC_BUFFER.init([0; 256]),
+        drone::rc::crsf_uart_config()
+    );
+
+    let adc = Adc::new(peripheral.ADC, Irqs, adc::Config::default());
+    let battery = adc::Channel::new_pin(peripheral.PIN_26, Pull::None);
+
+    let esc = Esc::new(
+        [
+            Pwm::new_output_ab(peripheral.PWM_SLICE1, peripheral.PIN_2, peripheral.PIN_3, pwm::Config::default()),
+            Pwm::new_output_ab(peripheral.PWM_SLICE2, peripheral.PIN_4, peripheral.PIN_5, pwm::Config::default())
+        ],
+        EscProtocol::OneShot125
+    );
 
-    let mpu6050 = Mpu6050::new_async(i2c);
-}
\ No newline at end of file
+    spawner.must_spawn(output_task(esc));
+    spawner.must_spawn(imu_task(imu, int_pin, i2c_bus));
+    spawner.must_spawn(rc_task(rc_rx));
+    spawner.must_spawn(battery_task(adc, battery));
+    spawner.must_spawn(telemetry_task());
+    spawner.must_spawn(flight_task(LOOP_HZ));
+}
@@ -0,0 +1,271 @@
+pub const MAX_MOTORS: usize = 6;
+
+/// Contribution of the roll, pitch and yaw commands to one motor.
+///
+/// Body axes are X forward, Y left, Z up and torques are right-handed about them,
+/// so positive roll lifts the left side, positive pitch lowers the nose and positive
+/// yaw turns the nose left. Props spinning clockwise seen from above get `yaw = 1`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MotorMix {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32
+}
+
+const fn mix(roll: f32, pitch: f32, yaw: f32) -> MotorMix {
+    MotorMix { roll, pitch, yaw }
+}
+
+/// Rear right, front right, rear left, front left.
+const QUAD_X: [MotorMix; 4] = [
+    mix(-1.0, 1.0, 1.0),
+    mix(-1.0, -1.0, -1.0),
+    mix(1.0, 1.0, -1.0),
+    mix(1.0, -1.0, 1.0)
+];
+
+/// Rear, right, left, front.
+const QUAD_PLUS: [MotorMix; 4] = [
+    mix(0.0, 1.0, 1.0),
+    mix(-1.0, 0.0, -1.0),
+    mix(1.0, 0.0, -1.0),
+    mix(0.0, -1.0, 1.0)
+];
+
+/// Rear right, front right, rear left, front left, right, left.
+const HEX_X: [MotorMix; 6] = [
+    mix(-0.5, 0.866_025, -1.0),
+    mix(-0.5, -0.866_025, -1.0),
+    mix(0.5, 0.866_025, 1.0),
+    mix(0.5, -0.866_025, 1.0),
+    mix(-1.0, 0.0, 1.0),
+    mix(1.0, 0.0, -1.0)
+];
+
+/// Rear, right, left. Yaw is produced by tilting the rear motor with a servo.
+const TRI: [MotorMix; 3] = [
+    mix(0.0, 1.333_333, 0.0),
+    mix(-1.0, -0.666_667, 0.0),
+    mix(1.0, -0.666_667, 0.0)
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum Frame {
+    QuadX,
+    QuadPlus,
+    HexX,
+    Tri
+}
+
+impl Frame {
+    pub fn table(self) -> &'static [MotorMix] {
+        match self {
+            Frame::QuadX => &QUAD_X,
+            Frame::QuadPlus => &QUAD_PLUS,
+            Frame::HexX => &HEX_X,
+            Frame::Tri => &TRI
+        }
+    }
+
+    pub fn motor_count(self) -> usize {
+        self.table().len()
+    }
+
+    pub fn has_yaw_servo(self) -> bool {
+        self == Frame::Tri
+    }
+}
+
+/// Normalized output range of one motor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MotorLimits {
+    /// Output while disarmed, the motor is stopped.
+    pub min: f32,
+    /// Output at zero throttle while armed, keeps the motor spinning.
+    pub idle: f32,
+    pub max: f32
+}
+
+impl Default for MotorLimits {
+    fn default() -> Self {
+        MotorLimits {
+            min: 0.0,
+            idle: 0.05,
+            max: 1.0
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct MixerOutput {
+    /// Motor commands in `[0, 1]`; entries past the frame's motor count are unused.
+    pub motors: [f32; MAX_MOTORS],
+    /// Yaw servo deflection in `[-1, 1]`, zero on frames without one.
+    pub servo: f32,
+    /// The attitude commands had to be scaled down to fit the motor range.
+    pub saturated: bool
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Mixer {
+    pub frame: Frame,
+    pub limits: [MotorLimits; MAX_MOTORS],
+    /// Let the mixer raise throttle above the stick to keep attitude authority near zero throttle.
+    pub airmode: bool,
+    pub servo_reversed: bool
+}
+
+impl Mixer {
+    pub fn new(frame: Frame) -> Self {
+        Mixer {
+            frame,
+            limits: [MotorLimits::default(); MAX_MOTORS],
+            airmode: true,
+            servo_reversed: false
+        }
+    }
+
+    pub fn motor_count(&self) -> usize {
+        self.frame.motor_count()
+    }
+
+    /// Output with every motor stopped.
+    pub fn stop(&self) -> MixerOutput {
+        let mut output = MixerOutput::default();
+        for (motor, limits) in output.motors.iter_mut().zip(self.limits.iter()).take(self.motor_count()) {
+            *motor = limits.min;
+        }
+        output
+    }
+
+    /// Mixes `throttle` in `[0, 1]` with roll, pitch and yaw commands in `[-1, 1]`.
+    pub fn mix(&self, throttle: f32, torque: [f32; 3]) -> MixerOutput {
+        let table = self.frame.table();
+        let throttle = throttle.clamp(0.0, 1.0);
+        let [roll, pitch, yaw] = torque;
+        let yaw_motors = if self.frame.has_yaw_servo() { 0.0 } else { yaw };
+
+        let mut attitude = [0.0f32; MAX_MOTORS];
+        for (value, factors) in attitude.iter_mut().zip(table) {
+            *value = roll * factors.roll + pitch * factors.pitch + yaw_motors * factors.yaw;
+        }
+        let used = &mut attitude[..table.len()];
+        let (mut low, mut high) = used.iter().fold((0.0f32, 0.0f32), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+
+        // Shrink the differential part until it fits in the motor range at all.
+        let mut saturated = false;
+        let range = high - low;
+        if range > 1.0 {
+            used.iter_mut().for_each(|v| *v /= range);
+            low /= range;
+            high /= range;
+            saturated = true;
+        }
+
+        let throttle = if self.airmode {
+            // Not `clamp`: after the rescale rounding can leave `-low` a hair above `1 - high`.
+            throttle.max(-low).min(1.0 - high)
+        } else {
+            if throttle + low < 0.0 {
+                let scale = throttle / -low;
+                used.iter_mut().for_each(|v| *v *= scale);
+                high *= scale;
+                saturated = true;
+            }
+            throttle.min(1.0 - high)
+        };
+
+        let mut output = MixerOutput {
+            servo: if self.frame.has_yaw_servo() {
+                (if self.servo_reversed { -yaw } else { yaw }).clamp(-1.0, 1.0)
+            } else {
+                0.0
+            },
+            saturated,
+            ..MixerOutput::default()
+        };
+        for ((motor, value), limits) in output.motors.iter_mut().zip(used.iter()).zip(self.limits.iter()) {
+            let command = (throttle + value).clamp(0.0, 1.0);
+            *motor = limits.idle + (limits.max - limits.idle) * command;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: [Frame; 4] = [Frame::QuadX, Frame::QuadPlus, Frame::HexX, Frame::Tri];
+
+    fn torques() -> impl Iterator<Item = [f32; 3]> {
+        let steps = [-1.0, -0.7, -0.3, 0.0, 0.1, 0.5, 1.0];
+        steps.into_iter().flat_map(move |r| {
+            steps.into_iter().flat_map(move |p| steps.into_iter().map(move |y| [r, p, y]))
+        })
+    }
+
+    #[test]
+    fn tables_have_no_net_torque_at_hover() {
+        for frame in FRAMES {
+            let table = frame.table();
+            assert!(table.iter().map(|m| m.roll).sum::<f32>().abs() < 1e-4, "{frame:?} roll");
+            assert!(table.iter().map(|m| m.pitch).sum::<f32>().abs() < 1e-4, "{frame:?} pitch");
+            assert!(table.iter().map(|m| m.yaw).sum::<f32>().abs() < 1e-4, "{frame:?} yaw");
+        }
+    }
+
+    #[test]
+    fn opposite_commands_mirror_motors() {
+        for frame in FRAMES {
+            let mixer = Mixer::new(frame);
+            let count = mixer.motor_count();
+            let hover = mixer.mix(0.5, [0.0; 3]);
+            let plus = mixer.mix(0.5, [0.2, -0.1, 0.15]);
+            let minus = mixer.mix(0.5, [-0.2, 0.1, -0.15]);
+            for motor in 0..count {
+                let up = plus.motors[motor] - hover.motors[motor];
+                let down = minus.motors[motor] - hover.motors[motor];
+                assert!((up + down).abs() < 1e-5, "{frame:?} motor {motor}");
+            }
+            assert_eq!(plus.servo, -minus.servo);
+        }
+    }
+
+    #[test]
+    fn roll_lifts_left_side() {
+        let output = Mixer::new(Frame::QuadX).mix(0.5, [0.2, 0.0, 0.0]);
+        // Rear right, front right, rear left, front left.
+        assert!(output.motors[2] > output.motors[0] && output.motors[3] > output.motors[1]);
+    }
+
+    #[test]
+    fn outputs_stay_in_range() {
+        for frame in FRAMES {
+            for airmode in [true, false] {
+                let mut mixer = Mixer::new(frame);
+                mixer.airmode = airmode;
+                for throttle in [0.0, 0.01, 0.3, 0.5, 0.97, 1.0] {
+                    for torque in torques() {
+                        let output = mixer.mix(throttle, torque);
+                        for &motor in &output.motors[..mixer.motor_count()] {
+                            assert!((0.05..=1.0).contains(&motor), "{frame:?} {throttle} {torque:?}: {motor}");
+                        }
+                        assert!((-1.0..=1.0).contains(&output.servo));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn saturated_mix_does_not_panic() {
+        // Ranges just above 1 used to leave the clamp bounds crossed after rescaling.
+        let mixer = Mixer::new(Frame::HexX);
+        for i in 0..1000 {
+            let k = 0.5 + i as f32 * 1e-4;
+            let output = mixer.mix(0.5, [k, -k, 0.3]);
+            assert!(output.motors[..6].iter().all(|m| m.is_finite()));
+        }
+    }
+}
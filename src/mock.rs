@@ -0,0 +1,152 @@
+//! Host-side test doubles: a scripted I2C bus and helpers for the mock time driver.
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use crate::errors::{BusError, I2cErrorExt};
+use embassy_futures::yield_now;
+use embassy_time::{Duration, MockDriver};
+use embedded_hal::i2c::{Error, ErrorKind, ErrorType, Operation};
+use embedded_hal_async::i2c::I2c;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+
+/// Error a scripted transaction fails with, either a generic kind or a raw RP2040 controller error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MockError {
+    Kind(ErrorKind),
+    Rp(embassy_rp::i2c::Error)
+}
+
+impl From<ErrorKind> for MockError {
+    fn from(value: ErrorKind) -> Self {
+        MockError::Kind(value)
+    }
+}
+
+impl From<embassy_rp::i2c::Error> for MockError {
+    fn from(value: embassy_rp::i2c::Error) -> Self {
+        MockError::Rp(value)
+    }
+}
+
+impl Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            MockError::Kind(kind) => *kind,
+            MockError::Rp(error) => error.kind()
+        }
+    }
+}
+
+impl I2cErrorExt for MockError {
+    fn bus_error(&self) -> BusError {
+        match self {
+            MockError::Kind(kind) => kind.bus_error(),
+            MockError::Rp(error) => error.bus_error()
+        }
+    }
+}
+
+/// One expected bus transaction and the bytes or error the device answers with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    address: u8,
+    write: Vec<u8>,
+    read: Option<Vec<u8>>,
+    error: Option<MockError>
+}
+
+impl Transaction {
+    pub fn write(address: u8, bytes: &[u8]) -> Self {
+        Transaction {
+            address,
+            write: bytes.to_vec(),
+            read: None,
+            error: None
+        }
+    }
+
+    pub fn write_read(address: u8, write: &[u8], read: &[u8]) -> Self {
+        Transaction {
+            address,
+            write: write.to_vec(),
+            read: Some(read.to_vec()),
+            error: None
+        }
+    }
+
+    /// Fails the transaction with `error` after checking it was the expected one.
+    pub fn with_error(mut self, error: impl Into<MockError>) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// I2C bus that asserts every transfer matches the next scripted transaction.
+#[derive(Debug, Default)]
+pub struct MockI2c {
+    expected: VecDeque<Transaction>
+}
+
+impl MockI2c {
+    pub fn new(expected: &[Transaction]) -> Self {
+        MockI2c {
+            expected: expected.iter().cloned().collect()
+        }
+    }
+
+    /// Asserts the whole script was consumed.
+    pub fn done(&self) {
+        assert!(self.expected.is_empty(), "unused transactions: {:02x?}", self.expected);
+    }
+}
+
+impl ErrorType for MockI2c {
+    type Error = MockError;
+}
+
+impl I2c for MockI2c {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), MockError> {
+        let expected = self.expected.pop_front().unwrap_or_else(|| panic!("unexpected transfer to {address:#04x}"));
+        assert_eq!(address, expected.address, "address");
+        let (write, read) = match operations {
+            [Operation::Write(write)] => (&**write, None),
+            [Operation::Write(write), Operation::Read(read)] => (&**write, Some(read)),
+            _ => panic!("unsupported operations {operations:?}")
+        };
+        assert_eq!(write, expected.write.as_slice(), "written bytes");
+        assert_eq!(read.is_some(), expected.read.is_some(), "read expected");
+        if let Some(error) = expected.error {
+            return Err(error)
+        }
+        if let (Some(read), Some(bytes)) = (read, expected.read) {
+            assert_eq!(read.len(), bytes.len(), "read length");
+            read.copy_from_slice(&bytes);
+        }
+        Ok(())
+    }
+}
+
+static TIME: Mutex<()> = Mutex::new(());
+
+/// Serialises tests that use the global mock clock. The clock is never reset because that would
+/// free the alarm the timer queue holds, so tests must measure from `Instant::now()`.
+pub fn time_lock() -> MutexGuard<'static, ()> {
+    TIME.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs `future` to completion, advancing the mock clock whenever it is pending.
+pub fn run<F: Future>(future: F) -> F::Output {
+    let _time = time_lock();
+    let clock = async {
+        loop {
+            MockDriver::get().advance(Duration::from_micros(100));
+            yield_now().await;
+        }
+    };
+    match block_on(select(future, clock)) {
+        Either::First(output) => output,
+        Either::Second(never) => never
+    }
+}
@@ -1,11 +1,25 @@
-use crate::errors::{DroneError, Result};
-use embassy_rp::i2c::{Async, I2c, Instance, Mode};
-use embassy_time::{Duration, Timer};
+use crate::bus::{BusPolicy, BusStats};
+use crate::errors::{BusError, DroneError, I2cErrorExt, Result};
+use embassy_time::{with_timeout, Duration, Timer};
 use modular_bitfield::bitfield;
 use modular_bitfield::prelude::*;
+use embedded_hal::digital::Error as _;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::I2c;
 
-pub const ADDR: u8 = 0xD0;
-pub const DEFAULT_SLAVE_ADDR: u8 = 0x68;
+pub mod calibration;
+pub mod i2c_master;
+pub mod power;
+pub mod self_test;
+
+use calibration::Calibration;
+
+/// `WHO_AM_I` value, independent of the AD0 pin.
+pub const CHIP_ID: u8 = 0x68;
+
+pub const STANDARD_GRAVITY: f32 = 9.80665;
+pub const SAMPLE_LEN: usize = 14;
+pub const FIFO_SIZE: usize = 1024;
 
 macro_rules! mpu6050_regs {
     ($($name:ident : $val:expr), * $(,)?) => {
@@ -15,6 +29,41 @@ macro_rules! mpu6050_regs {
     };
 }
 
+/// 7-bit I2C address selected by the AD0 pin.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum Address {
+    #[default]
+    Ad0Low = 0x68,
+    Ad0High = 0x69
+}
+
+/// A bitfield type backed by a single register.
+pub trait Register: Copy {
+    const ADDR: u8;
+
+    fn from_byte(byte: u8) -> Self;
+
+    fn to_byte(self) -> u8;
+}
+
+macro_rules! registers {
+    ($($ty:ty : $addr:expr), * $(,)?) => {
+        $(
+            impl Register for $ty {
+                const ADDR: u8 = $addr;
+
+                fn from_byte(byte: u8) -> Self {
+                    <$ty>::from_bytes([byte])
+                }
+
+                fn to_byte(self) -> u8 {
+                    self.bytes[0]
+                }
+            }
+        )*
+    };
+}
+
 mpu6050_regs! {
     SELF_TEST_X: 0x0D,
     SELF_TEST_Y: 0x0E,
@@ -100,18 +149,137 @@ mpu6050_regs! {
     WHO_AM_I: 0x75,
 }
 
+registers! {
+    Config: CONFIG,
+    PwrMgmt1: PWR_MGMT_1,
+    PwrMgmt2: PWR_MGMT_2,
+    AccelConfig: ACCEL_CONFIG,
+    GyroConfig: GYRO_CONFIG,
+    FifoEn: FIFO_EN,
+    UserCtrl: USER_CTRL,
+    IntPinCfg: INT_PIN_CFG,
+    IntEnable: INT_ENABLE,
+    IntStatus: INT_STATUS,
+    SignalPathReset: SIGNAL_PATH_RESET,
+    I2cMstCtrl: I2C_MST_CTRL,
+    I2cSlv4Ctrl: I2C_SLV4_CTRL,
+    I2cMstStatus: I2C_MST_STATUS,
+    I2cMstDelayCtrl: I2C_MST_DELAY_CTRL,
+}
+
 #[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Config {
+    #[bits = 3]
+    pub dlpf_cfg: DlpfBandwidth,
+    #[bits = 3]
+    pub ext_sync_set: ExtSync,
+    #[skip] __: B2
+}
+
+/// Accel/gyro bandwidth of the digital low-pass filter.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
+pub enum DlpfBandwidth {
+    Hz260 = 0,
+    Hz184,
+    Hz94,
+    Hz44,
+    Hz21,
+    Hz10,
+    Hz5,
+    Reserved
+}
+
+impl DlpfBandwidth {
+    /// Gyro output rate feeding the sample rate divider.
+    pub fn gyro_output_rate(self) -> u32 {
+        match self {
+            DlpfBandwidth::Hz260 | DlpfBandwidth::Reserved => 8000,
+            _ => 1000
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
+pub enum ExtSync {
+    Disabled = 0,
+    TempOutL,
+    GyroXoutL,
+    GyroYoutL,
+    GyroZoutL,
+    AccelXoutL,
+    AccelYoutL,
+    AccelZoutL
+}
+
+/// Output data rate, `gyro_output_rate / (1 + divider)`.
+/// The accelerometer is sampled at 1 kHz, so rates above that repeat accel readings.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct SampleRate {
+    pub dlpf: DlpfBandwidth,
+    pub divider: u8
+}
+
+impl SampleRate {
+    /// Closest rate not above `hz` that the divider can produce with `dlpf`.
+    pub fn from_hz(hz: u32, dlpf: DlpfBandwidth) -> Self {
+        let base = dlpf.gyro_output_rate();
+        let divider = base.div_ceil(hz.max(1)).clamp(1, 256) - 1;
+        SampleRate {
+            dlpf,
+            divider: divider as u8
+        }
+    }
+
+    pub fn hz(&self) -> f32 {
+        self.dlpf.gyro_output_rate() as f32 / (1 + self.divider as u32) as f32
+    }
+}
+
+impl Default for SampleRate {
+    fn default() -> Self {
+        SampleRate {
+            dlpf: DlpfBandwidth::Hz260,
+            divider: 0
+        }
+    }
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct PwrMgmt1 {
     #[bits = 3]
-    clksel: ClockSource,
-    temp_dis: bool,
+    pub clksel: ClockSource,
+    pub temp_dis: bool,
     #[skip] __: B1,
-    cycle: bool,
-    sleep: bool,
-    device_reset: bool
+    pub cycle: bool,
+    pub sleep: bool,
+    pub device_reset: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PwrMgmt2 {
+    pub stby_zg: bool,
+    pub stby_yg: bool,
+    pub stby_xg: bool,
+    pub stby_za: bool,
+    pub stby_ya: bool,
+    pub stby_xa: bool,
+    #[bits = 2]
+    pub lp_wake_ctrl: LpWakeCtrl
+}
+
+/// Accelerometer wake-up frequency in cycle mode.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
+pub enum LpWakeCtrl {
+    Hz1_25 = 0,
+    Hz5,
+    Hz20,
+    Hz40
 }
 
-#[derive(BitfieldSpecifier)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
 pub enum ClockSource {
     Internal8MHz = 0,
     PllXAxis = 1,
@@ -124,13 +292,14 @@ pub enum ClockSource {
 }
 
 #[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct AccelConfig {
     #[skip] __: B3,
     #[bits = 2]
-    afs_sel: AccelRange,
-    za_st: bool,
-    ya_st: bool,
-    xa_st: bool
+    pub afs_sel: AccelRange,
+    pub za_st: bool,
+    pub ya_st: bool,
+    pub xa_st: bool
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
@@ -142,16 +311,29 @@ pub enum AccelRange {
 }
 
 #[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
 pub struct GyroConfig {
     #[skip] __: B3,
     #[bits = 2]
-    fs_sel: GyroRange,
-    zg_st: bool,
-    yg_st: bool,
-    xg_st: bool
+    pub fs_sel: GyroRange,
+    pub zg_st: bool,
+    pub yg_st: bool,
+    pub xg_st: bool
 }
 
-#[derive(BitfieldSpecifier)]
+impl AccelRange {
+    /// LSB per g.
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            AccelRange::G2 => 16384.0,
+            AccelRange::G4 => 8192.0,
+            AccelRange::G8 => 4096.0,
+            AccelRange::G16 => 2048.0
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
 pub enum GyroRange {
     D250 = 0,
     D500,
@@ -159,23 +341,366 @@ pub enum GyroRange {
     D2000
 }
 
-pub struct Mpu6050<'d, T: Instance, M: Mode> {
-    i2c: I2c<'d, T, M>
+impl GyroRange {
+    /// LSB per °/s.
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            GyroRange::D250 => 131.0,
+            GyroRange::D500 => 65.5,
+            GyroRange::D1000 => 32.8,
+            GyroRange::D2000 => 16.4
+        }
+    }
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FifoEn {
+    pub slv0_fifo_en: bool,
+    pub slv1_fifo_en: bool,
+    pub slv2_fifo_en: bool,
+    pub accel_fifo_en: bool,
+    pub zg_fifo_en: bool,
+    pub yg_fifo_en: bool,
+    pub xg_fifo_en: bool,
+    pub temp_fifo_en: bool
+}
+
+impl FifoEn {
+    /// Accel, temperature and gyro, laid out like a [`Sample`] burst.
+    pub fn all_sensors() -> Self {
+        FifoEn::new()
+            .with_accel_fifo_en(true)
+            .with_temp_fifo_en(true)
+            .with_xg_fifo_en(true)
+            .with_yg_fifo_en(true)
+            .with_zg_fifo_en(true)
+    }
+
+    /// Whether any aux slave channel pushes its data into the FIFO.
+    pub fn has_slave_data(&self) -> bool {
+        self.slv0_fifo_en() || self.slv1_fifo_en() || self.slv2_fifo_en()
+    }
+
+    /// Bytes pushed per sample period, not counting aux slave data.
+    pub fn frame_len(&self) -> usize {
+        let mut len = 0;
+        if self.accel_fifo_en() { len += 6; }
+        if self.temp_fifo_en() { len += 2; }
+        if self.xg_fifo_en() { len += 2; }
+        if self.yg_fifo_en() { len += 2; }
+        if self.zg_fifo_en() { len += 2; }
+        len
+    }
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UserCtrl {
+    pub sig_cond_reset: bool,
+    pub i2c_mst_reset: bool,
+    pub fifo_reset: bool,
+    #[skip] __: B1,
+    pub i2c_if_dis: bool,
+    pub i2c_mst_en: bool,
+    pub fifo_en: bool,
+    #[skip] __: B1
 }
 
-impl<'d, T: Instance> Mpu6050<'d, T, Async> {
-    pub fn new_async(i2c: I2c<'d, T, Async>) -> Self {
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IntPinCfg {
+    #[skip] __: B1,
+    pub i2c_bypass_en: bool,
+    pub fsync_int_en: bool,
+    pub fsync_int_level: bool,
+    pub int_rd_clear: bool,
+    pub latch_int_en: bool,
+    pub int_open: bool,
+    pub int_level: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IntEnable {
+    pub data_rdy_en: bool,
+    #[skip] __: B2,
+    pub i2c_mst_int_en: bool,
+    pub fifo_oflow_en: bool,
+    #[skip] __: B3
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SignalPathReset {
+    pub temp_reset: bool,
+    pub accel_reset: bool,
+    pub gyro_reset: bool,
+    #[skip] __: B5
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IntStatus {
+    pub data_rdy_int: bool,
+    #[skip] __: B2,
+    pub i2c_mst_int: bool,
+    pub fifo_oflow_int: bool,
+    #[skip] __: B3
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cMstCtrl {
+    #[bits = 4]
+    pub i2c_mst_clk: MasterClock,
+    pub i2c_mst_p_nsr: bool,
+    pub slv_3_fifo_en: bool,
+    pub wait_for_es: bool,
+    pub mult_mst_en: bool
+}
+
+/// Aux bus clock, derived from the 8 MHz internal clock.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, BitfieldSpecifier)]
+pub enum MasterClock {
+    Khz348 = 0,
+    Khz333,
+    Khz320,
+    Khz308,
+    Khz296,
+    Khz286,
+    Khz276,
+    Khz267,
+    Khz258,
+    Khz500,
+    Khz471,
+    Khz444,
+    Khz421,
+    Khz400,
+    Khz381,
+    Khz364
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cSlvAddr {
+    pub i2c_slv_addr: B7,
+    pub i2c_slv_rw: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cSlvCtrl {
+    pub i2c_slv_len: B4,
+    pub i2c_slv_grp: bool,
+    pub i2c_slv_reg_dis: bool,
+    pub i2c_slv_byte_sw: bool,
+    pub i2c_slv_en: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cSlv4Ctrl {
+    pub i2c_mst_dly: B5,
+    pub i2c_slv4_reg_dis: bool,
+    pub slv4_int_en: bool,
+    pub i2c_slv4_en: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cMstStatus {
+    pub i2c_slv0_nack: bool,
+    pub i2c_slv1_nack: bool,
+    pub i2c_slv2_nack: bool,
+    pub i2c_slv3_nack: bool,
+    pub i2c_slv4_nack: bool,
+    pub i2c_lost_arb: bool,
+    pub i2c_slv4_done: bool,
+    pub pass_through: bool
+}
+
+#[bitfield]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct I2cMstDelayCtrl {
+    pub i2c_slv0_dly_en: bool,
+    pub i2c_slv1_dly_en: bool,
+    pub i2c_slv2_dly_en: bool,
+    pub i2c_slv3_dly_en: bool,
+    pub i2c_slv4_dly_en: bool,
+    #[skip] __: B2,
+    pub delay_es_shadow: bool
+}
+
+/// Raw burst of `ACCEL_XOUT_H..=GYRO_ZOUT_L`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub accel: [i16; 3],
+    pub temp: i16,
+    pub gyro: [i16; 3]
+}
+
+impl Sample {
+    pub fn from_bytes(bytes: &[u8; SAMPLE_LEN]) -> Self {
+        let word = |i: usize| i16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        Sample {
+            accel: [word(0), word(2), word(4)],
+            temp: word(6),
+            gyro: [word(8), word(10), word(12)]
+        }
+    }
+
+    pub fn scale(&self, accel_range: AccelRange, gyro_range: GyroRange) -> ScaledSample {
+        let accel_scale = STANDARD_GRAVITY / accel_range.sensitivity();
+        let gyro_scale = (core::f32::consts::PI / 180.0) / gyro_range.sensitivity();
+        ScaledSample {
+            accel: self.accel.map(|v| v as f32 * accel_scale),
+            temp: self.temp as f32 / 340.0 + 36.53,
+            gyro: self.gyro.map(|v| v as f32 * gyro_scale)
+        }
+    }
+}
+
+/// Sample in m/s², °C and rad/s.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ScaledSample {
+    pub accel: [f32; 3],
+    pub temp: f32,
+    pub gyro: [f32; 3]
+}
+
+/// Chip values that differ from the driver's cached configuration.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ConfigMismatch {
+    pub accel_range: Option<AccelRange>,
+    pub gyro_range: Option<GyroRange>,
+    pub clock_source: Option<ClockSource>,
+    pub sample_rate: Option<SampleRate>,
+    pub sleeping: bool
+}
+
+impl ConfigMismatch {
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigMismatch::default()
+    }
+}
+
+/// Driver for one MPU6050. `I` may own the bus or be a shared-bus device
+/// such as `embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice`.
+pub struct Mpu6050<I> {
+    i2c: I,
+    address: Address,
+    policy: BusPolicy,
+    stats: BusStats,
+    accel_range: AccelRange,
+    gyro_range: GyroRange,
+    clock_source: ClockSource,
+    fifo: FifoEn,
+    int_pin_cfg: IntPinCfg,
+    int_enable: IntEnable,
+    sample_rate: SampleRate,
+    calibration: Calibration,
+    /// `temp_dis` and `PWR_MGMT_2` from before cycle mode was entered.
+    cycle_restore: Option<(bool, PwrMgmt2)>
+}
+
+impl<I: I2c> Mpu6050<I>
+where
+    I::Error: I2cErrorExt
+{
+    pub fn new(i2c: I, address: Address) -> Self {
         Mpu6050 {
-            i2c
+            i2c,
+            address,
+            policy: BusPolicy::default(),
+            stats: BusStats::default(),
+            accel_range: AccelRange::G2,
+            gyro_range: GyroRange::D250,
+            clock_source: ClockSource::Internal8MHz,
+            fifo: FifoEn::new(),
+            int_pin_cfg: IntPinCfg::new(),
+            int_enable: IntEnable::new(),
+            sample_rate: SampleRate::default(),
+            calibration: Calibration::default(),
+            cycle_restore: None
         }
     }
 
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn set_bus_policy(&mut self, policy: BusPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn bus_stats(&self) -> BusStats {
+        self.stats
+    }
+
+    pub fn accel_range(&self) -> AccelRange {
+        self.accel_range
+    }
+
+    pub fn gyro_range(&self) -> GyroRange {
+        self.gyro_range
+    }
+
+    pub fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    pub async fn read_accel_range(&mut self) -> Result<AccelRange> {
+        let accel_config: AccelConfig = self.read_register().await?;
+        Ok(accel_config.afs_sel())
+    }
+
+    pub async fn read_gyro_range(&mut self) -> Result<GyroRange> {
+        let gyro_config: GyroConfig = self.read_register().await?;
+        Ok(gyro_config.fs_sel())
+    }
+
+    pub async fn read_clock_source(&mut self) -> Result<ClockSource> {
+        let pwr_mgmt1: PwrMgmt1 = self.read_register().await?;
+        Ok(pwr_mgmt1.clksel())
+    }
+
+    /// Compares the chip against the cached configuration, e.g. after a brownout.
+    pub async fn verify_config(&mut self) -> Result<ConfigMismatch> {
+        let accel_range = self.read_accel_range().await?;
+        let gyro_range = self.read_gyro_range().await?;
+        let pwr_mgmt1: PwrMgmt1 = self.read_register().await?;
+        let sample_rate = self.sample_rate().await?;
+        Ok(ConfigMismatch {
+            accel_range: (accel_range != self.accel_range).then_some(accel_range),
+            gyro_range: (gyro_range != self.gyro_range).then_some(gyro_range),
+            clock_source: (pwr_mgmt1.clksel() != self.clock_source).then_some(pwr_mgmt1.clksel()),
+            sample_rate: (sample_rate != self.sample_rate).then_some(sample_rate),
+            sleeping: pwr_mgmt1.sleep()
+        })
+    }
+
+    /// Writes the cached configuration to the chip and wakes it.
+    /// Aux I2C master channels are not cached and have to be set up again by the caller.
+    pub async fn apply_config(&mut self) -> Result<()> {
+        let clock_source = self.clock_source;
+        self.modify_register(|r: PwrMgmt1| r.with_sleep(false).with_clksel(clock_source)).await?;
+        self.set_accel_range(self.accel_range).await?;
+        self.set_gyro_range(self.gyro_range).await?;
+        self.configure_sample_rate(self.sample_rate).await?;
+        self.configure_interrupts(self.int_pin_cfg, self.int_enable).await?;
+        if self.fifo.frame_len() > 0 {
+            self.configure_fifo(self.fifo).await?;
+        }
+        Ok(())
+    }
+
     pub async fn id(&mut self) -> Result<u8> {
         self.read(WHO_AM_I).await
     }
 
     pub async fn wake(&mut self, delay: Duration) -> Result<()> {
-        self.write(PWR_MGMT_1, &[0x0]).await?;
+        self.modify_register(|r: PwrMgmt1| r.with_sleep(false)).await?;
         Timer::after(delay).await;
         Ok(())
     }
@@ -201,58 +726,451 @@ impl<'d, T: Instance> Mpu6050<'d, T, Async> {
 
     pub async fn verify(&mut self) -> Result<()> {
         let id = self.id().await?;
-        if id != DEFAULT_SLAVE_ADDR {
+        if id != CHIP_ID {
             return Err(DroneError::InvalidChipId(id))
         }
         Ok(())
     }
 
     pub async fn set_accel_range(&mut self, accel_range: AccelRange) -> Result<()> {
-        let accel_config = AccelConfig::new().with_afs_sel(accel_range);
-        self.write(ACCEL_CONFIG, &accel_config.bytes).await?;
+        self.modify_register(|r: AccelConfig| r.with_afs_sel(accel_range)).await?;
+        self.accel_range = accel_range;
         Ok(())
     }
 
     pub async fn set_accel_range_with_self_test(&mut self, accel_range: AccelRange) -> Result<()> {
-        let accel_config = AccelConfig::new()
+        self.modify_register(|r: AccelConfig| r
             .with_afs_sel(accel_range)
             .with_xa_st(true)
             .with_ya_st(true)
-            .with_za_st(true);
-        self.write(ACCEL_CONFIG, &accel_config.bytes).await?;
+            .with_za_st(true)
+        ).await?;
+        self.accel_range = accel_range;
         Ok(())
     }
 
     pub async fn set_gyro_range(&mut self, gyro_range: GyroRange) -> Result<()> {
-        let gyro_config = GyroConfig::new().with_fs_sel(gyro_range);
-        self.write(GYRO_CONFIG, &gyro_config.bytes).await?;
+        self.modify_register(|r: GyroConfig| r.with_fs_sel(gyro_range)).await?;
+        self.gyro_range = gyro_range;
         Ok(())
     }
 
     pub async fn set_gyro_range_with_self_test(&mut self, gyro_range: GyroRange) -> Result<()> {
-        let gyro_config = GyroConfig::new()
+        self.modify_register(|r: GyroConfig| r
             .with_fs_sel(gyro_range)
             .with_xg_st(true)
             .with_yg_st(true)
-            .with_zg_st(true);
-        self.write(GYRO_CONFIG, &gyro_config.bytes).await?;
+            .with_zg_st(true)
+        ).await?;
+        self.gyro_range = gyro_range;
+        Ok(())
+    }
+
+    /// Clears the self-test bits of both sensors, leaving their ranges untouched.
+    pub async fn disable_self_test(&mut self) -> Result<()> {
+        self.modify_register(|r: AccelConfig| r.with_xa_st(false).with_ya_st(false).with_za_st(false)).await?;
+        self.modify_register(|r: GyroConfig| r.with_xg_st(false).with_yg_st(false).with_zg_st(false)).await?;
         Ok(())
     }
 
+    pub async fn set_dlpf(&mut self, dlpf: DlpfBandwidth) -> Result<()> {
+        self.modify_register(|r: Config| r.with_dlpf_cfg(dlpf)).await?;
+        self.sample_rate.dlpf = dlpf;
+        Ok(())
+    }
+
+    pub async fn configure_sample_rate(&mut self, sample_rate: SampleRate) -> Result<()> {
+        self.set_dlpf(sample_rate.dlpf).await?;
+        self.write(SMPLRT_DIV, &[sample_rate.divider]).await?;
+        self.sample_rate = sample_rate;
+        Ok(())
+    }
+
+    /// Sets the divider for the requested rate under the current DLPF setting.
+    pub async fn set_sample_rate(&mut self, hz: u32) -> Result<SampleRate> {
+        let sample_rate = SampleRate::from_hz(hz, self.sample_rate.dlpf);
+        self.write(SMPLRT_DIV, &[sample_rate.divider]).await?;
+        self.sample_rate = sample_rate;
+        Ok(sample_rate)
+    }
+
+    /// Effective rate as currently configured on the chip.
+    pub async fn sample_rate(&mut self) -> Result<SampleRate> {
+        let config: Config = self.read_register().await?;
+        let divider = self.read(SMPLRT_DIV).await?;
+        Ok(SampleRate {
+            dlpf: config.dlpf_cfg(),
+            divider
+        })
+    }
+
     pub async fn set_clock_source(&mut self, source: ClockSource) -> Result<()> {
-        let pwr_mgmt1 = PwrMgmt1::new().with_clksel(source);
-        self.write(PWR_MGMT_1, &pwr_mgmt1.bytes).await?;
+        self.modify_register(|r: PwrMgmt1| r.with_clksel(source)).await?;
+        self.clock_source = source;
         Ok(())
     }
 
-    async fn write(&mut self, reg: u8, bits: &[u8; 1]) -> Result<()> {
-        self.i2c.write_async(ADDR, [reg, bits[0]]).await?;
+    pub async fn read_sample(&mut self) -> Result<Sample> {
+        let mut data = [0; SAMPLE_LEN];
+        self.read_bytes(ACCEL_XOUT_H, &mut data).await?;
+        Ok(Sample::from_bytes(&data))
+    }
+
+    pub async fn read_scaled(&mut self) -> Result<ScaledSample> {
+        let mut sample = self.read_sample().await?.scale(self.accel_range, self.gyro_range);
+        self.calibration.apply(&mut sample);
+        Ok(sample)
+    }
+
+    /// Aux slave data is rejected because its length is set per channel and would misalign frames.
+    pub async fn configure_fifo(&mut self, fifo: FifoEn) -> Result<()> {
+        if fifo.has_slave_data() {
+            return Err(DroneError::FifoSlaveData)
+        }
+        self.write_register(FifoEn::new()).await?;
+        self.fifo = fifo;
+        self.reset_fifo().await?;
+        self.write_register(fifo).await?;
+        Ok(())
+    }
+
+    pub async fn disable_fifo(&mut self) -> Result<()> {
+        self.write_register(FifoEn::new()).await?;
+        self.modify_register(|r: UserCtrl| r.with_fifo_en(false)).await?;
+        self.fifo = FifoEn::new();
         Ok(())
     }
 
+    pub async fn reset_fifo(&mut self) -> Result<()> {
+        let enabled = self.fifo.frame_len() > 0;
+        self.modify_register(|r: UserCtrl| r.with_fifo_en(false).with_fifo_reset(true)).await?;
+        self.modify_register(|r: UserCtrl| r.with_fifo_en(enabled)).await?;
+        Ok(())
+    }
+
+    pub async fn int_status(&mut self) -> Result<IntStatus> {
+        self.read_register().await
+    }
+
+    pub async fn configure_interrupts(&mut self, pin_cfg: IntPinCfg, enable: IntEnable) -> Result<()> {
+        self.write_register(pin_cfg).await?;
+        self.write_register(enable).await?;
+        self.int_pin_cfg = pin_cfg;
+        self.int_enable = enable;
+        Ok(())
+    }
+
+    /// Latched, active-high data-ready interrupt that is cleared by the next sample read.
+    pub async fn enable_data_ready_interrupt(&mut self) -> Result<()> {
+        let pin_cfg = IntPinCfg::new()
+            .with_latch_int_en(true)
+            .with_int_rd_clear(true);
+        self.configure_interrupts(pin_cfg, IntEnable::new().with_data_rdy_en(true)).await
+    }
+
+    /// Sleeps until the INT pin signals data ready, then reads the sample.
+    /// A latched interrupt is awaited by level so an edge missed while busy cannot stall the loop.
+    pub async fn wait_for_data_ready<P: Wait>(&mut self, int_pin: &mut P) -> Result<Sample> {
+        let active_low = self.int_pin_cfg.int_level();
+        let waited = match (self.int_pin_cfg.latch_int_en(), active_low) {
+            (true, false) => int_pin.wait_for_high().await,
+            (true, true) => int_pin.wait_for_low().await,
+            (false, false) => int_pin.wait_for_rising_edge().await,
+            (false, true) => int_pin.wait_for_falling_edge().await
+        };
+        waited.map_err(|e| DroneError::Gpio(e.kind()))?;
+        self.read_sample().await
+    }
+
+    pub async fn fifo_count(&mut self) -> Result<u16> {
+        let mut data = [0; 2];
+        self.read_bytes(FIFO_COUNTH, &mut data).await?;
+        Ok(u16::from_be_bytes(data))
+    }
+
+    /// Drains as many whole frames as fit into `buf` and returns how many were read.
+    /// On overflow the FIFO is reset before returning [`DroneError::FifoOverflow`].
+    pub async fn read_fifo(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let frame_len = self.fifo.frame_len();
+        if frame_len == 0 {
+            return Err(DroneError::FifoDisabled)
+        }
+        let count = self.fifo_count().await? as usize;
+        if count >= FIFO_SIZE || self.int_status().await?.fifo_oflow_int() {
+            self.reset_fifo().await?;
+            return Err(DroneError::FifoOverflow)
+        }
+        let frames = count.min(buf.len()) / frame_len;
+        if frames > 0 {
+            // A failed burst may have popped part of a frame, so realign on an empty FIFO.
+            if let Err(error) = self.read_bytes(FIFO_R_W, &mut buf[..frames * frame_len]).await {
+                self.reset_fifo().await?;
+                return Err(error)
+            }
+        }
+        Ok(frames)
+    }
+
+    pub async fn read_register<R: Register>(&mut self) -> Result<R> {
+        Ok(R::from_byte(self.read(R::ADDR).await?))
+    }
+
+    pub async fn write_register<R: Register>(&mut self, value: R) -> Result<()> {
+        self.write(R::ADDR, &[value.to_byte()]).await
+    }
+
+    /// Reads the register, applies `f` and writes the result back, so fields `f` does not touch keep their value.
+    pub async fn modify_register<R: Register>(&mut self, f: impl FnOnce(R) -> R) -> Result<R> {
+        let value = f(self.read_register().await?);
+        self.write_register(value).await?;
+        Ok(value)
+    }
+
+    async fn write(&mut self, reg: u8, bits: &[u8; 1]) -> Result<()> {
+        self.transfer(reg, &[reg, bits[0]], &mut []).await
+    }
+
     async fn read(&mut self, reg: u8) -> Result<u8> {
         let mut data = [0; 1];
-        self.i2c.write_read_async(ADDR, [reg], &mut data).await?;
+        self.transfer(reg, &[reg], &mut data).await?;
         Ok(data[0])
     }
-}
\ No newline at end of file
+
+    async fn read_bytes(&mut self, reg: u8, data: &mut [u8]) -> Result<()> {
+        self.transfer(reg, &[reg], data).await
+    }
+
+    /// Writes `write`, then reads into `read` unless it is empty, applying the bus policy.
+    /// Reads that consume data or clear status bits on the chip are never retried.
+    async fn transfer(&mut self, reg: u8, write: &[u8], read: &mut [u8]) -> Result<()> {
+        let address = self.address as u8;
+        let destructive = !read.is_empty() && matches!(reg, FIFO_R_W | INT_STATUS | I2C_MST_STATUS);
+        let retries = if destructive { 0 } else { self.policy.retries };
+        let timeout = self.policy.timeout_for(write.len() + read.len());
+        let mut attempt = 0;
+        loop {
+            self.stats.transactions = self.stats.transactions.wrapping_add(1);
+            let result = if read.is_empty() {
+                with_timeout(timeout, self.i2c.write(address, write)).await
+            } else {
+                with_timeout(timeout, self.i2c.write_read(address, write, read)).await
+            };
+            let error = match result {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => e.bus_error(),
+                Err(_) => {
+                    self.stats.timeouts = self.stats.timeouts.wrapping_add(1);
+                    BusError::Timeout
+                }
+            };
+            if !error.is_transient() || attempt >= retries {
+                self.stats.failures = self.stats.failures.wrapping_add(1);
+                return Err(DroneError::I2c { reg, error })
+            }
+            attempt += 1;
+            self.stats.retries = self.stats.retries.wrapping_add(1);
+            Timer::after(self.policy.backoff * attempt as u32).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, MockI2c, Transaction};
+    use embassy_rp::i2c::AbortReason;
+    use embedded_hal::i2c::ErrorKind;
+
+    const ADDR: u8 = Address::Ad0Low as u8;
+
+    fn read(reg: u8, value: u8) -> Transaction {
+        Transaction::write_read(ADDR, &[reg], &[value])
+    }
+
+    fn write(reg: u8, value: u8) -> Transaction {
+        Transaction::write(ADDR, &[reg, value])
+    }
+
+    #[test]
+    fn verify_accepts_chip_id() {
+        let mut imu = Mpu6050::new(MockI2c::new(&[read(WHO_AM_I, CHIP_ID)]), Address::Ad0Low);
+        assert_eq!(run(imu.verify()), Ok(()));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn verify_rejects_other_id() {
+        let mut imu = Mpu6050::new(MockI2c::new(&[read(WHO_AM_I, 0x70)]), Address::Ad0Low);
+        assert_eq!(run(imu.verify()), Err(DroneError::InvalidChipId(0x70)));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn verify_uses_ad0_high_address() {
+        let i2c = MockI2c::new(&[Transaction::write_read(0x69, &[WHO_AM_I], &[CHIP_ID])]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0High);
+        assert_eq!(run(imu.verify()), Ok(()));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn set_accel_range_keeps_self_test_bits() {
+        let i2c = MockI2c::new(&[read(ACCEL_CONFIG, 0xe0), write(ACCEL_CONFIG, 0xf0)]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert_eq!(run(imu.set_accel_range(AccelRange::G8)), Ok(()));
+        assert_eq!(imu.accel_range(), AccelRange::G8);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn set_accel_range_failure_keeps_cached_range() {
+        let i2c = MockI2c::new(&[
+            read(ACCEL_CONFIG, 0x00),
+            write(ACCEL_CONFIG, 0x18).with_error(ErrorKind::Other)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert!(run(imu.set_accel_range(AccelRange::G16)).is_err());
+        assert_eq!(imu.accel_range(), AccelRange::G2);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let i2c = MockI2c::new(&[
+            read(WHO_AM_I, 0).with_error(ErrorKind::Bus),
+            read(WHO_AM_I, CHIP_ID)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert_eq!(run(imu.verify()), Ok(()));
+        assert_eq!(imu.bus_stats().retries, 1);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn int_status_is_not_retried() {
+        let mut imu = Mpu6050::new(MockI2c::new(&[read(INT_STATUS, 0).with_error(ErrorKind::Bus)]), Address::Ad0Low);
+        assert_eq!(
+            run(imu.int_status()).map(|_| ()),
+            Err(DroneError::I2c { reg: INT_STATUS, error: BusError::Bus })
+        );
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn i2c_mst_status_is_not_retried() {
+        let i2c = MockI2c::new(&[read(I2C_MST_STATUS, 0).with_error(ErrorKind::Bus)]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert_eq!(
+            run(imu.read_register::<I2cMstStatus>()).map(|_| ()),
+            Err(DroneError::I2c { reg: I2C_MST_STATUS, error: BusError::Bus })
+        );
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn data_nack_is_retried() {
+        // embassy-rp reports a NACKed data byte as a raw abort reason.
+        let nack = embassy_rp::i2c::Error::Abort(AbortReason::Other(1 << 3));
+        let i2c = MockI2c::new(&[
+            write(PWR_MGMT_1, 0x01).with_error(nack),
+            write(PWR_MGMT_1, 0x01)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert_eq!(run(imu.write_register(PwrMgmt1::new().with_clksel(ClockSource::PllXAxis))), Ok(()));
+        assert_eq!(imu.bus_stats().retries, 1);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn failed_fifo_read_resets_fifo() {
+        let i2c = MockI2c::new(&[
+            Transaction::write_read(ADDR, &[FIFO_COUNTH], &[0x00, 28]),
+            read(INT_STATUS, 0x00),
+            Transaction::write_read(ADDR, &[FIFO_R_W], &[0; 28]).with_error(ErrorKind::Bus),
+            read(USER_CTRL, 0x40),
+            write(USER_CTRL, 0x04),
+            read(USER_CTRL, 0x00),
+            write(USER_CTRL, 0x40)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        imu.fifo = FifoEn::all_sensors();
+        let mut buf = [0; 64];
+        assert_eq!(
+            run(imu.read_fifo(&mut buf)),
+            Err(DroneError::I2c { reg: FIFO_R_W, error: BusError::Bus })
+        );
+        assert_eq!(imu.bus_stats().retries, 0);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn configure_fifo_rejects_slave_data() {
+        let mut imu = Mpu6050::new(MockI2c::new(&[]), Address::Ad0Low);
+        let fifo = FifoEn::all_sensors().with_slv0_fifo_en(true);
+        assert_eq!(run(imu.configure_fifo(fifo)), Err(DroneError::FifoSlaveData));
+        assert_eq!(imu.fifo.frame_len(), 0);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn full_fifo_count_resets_fifo() {
+        let i2c = MockI2c::new(&[
+            Transaction::write_read(ADDR, &[FIFO_COUNTH], &[0x04, 0x00]),
+            read(USER_CTRL, 0x40),
+            write(USER_CTRL, 0x04),
+            read(USER_CTRL, 0x00),
+            write(USER_CTRL, 0x40)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        imu.fifo = FifoEn::all_sensors();
+        let mut buf = [0; 64];
+        assert_eq!(run(imu.read_fifo(&mut buf)), Err(DroneError::FifoOverflow));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn overflow_interrupt_resets_fifo() {
+        let i2c = MockI2c::new(&[
+            Transaction::write_read(ADDR, &[FIFO_COUNTH], &[0x00, 28]),
+            read(INT_STATUS, 0x10),
+            read(USER_CTRL, 0x40),
+            write(USER_CTRL, 0x04),
+            read(USER_CTRL, 0x00),
+            write(USER_CTRL, 0x40)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        imu.fifo = FifoEn::all_sensors();
+        let mut buf = [0; 64];
+        assert_eq!(run(imu.read_fifo(&mut buf)), Err(DroneError::FifoOverflow));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn init_with_gyro_accel_range_writes_ranges() {
+        let i2c = MockI2c::new(&[
+            read(PWR_MGMT_1, 0x40),
+            write(PWR_MGMT_1, 0x00),
+            read(WHO_AM_I, CHIP_ID),
+            read(ACCEL_CONFIG, 0x00),
+            write(ACCEL_CONFIG, 0x10),
+            read(GYRO_CONFIG, 0x00),
+            write(GYRO_CONFIG, 0x18)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        let init = imu.init_with_gyro_accel_range(Duration::from_millis(100), AccelRange::G8, GyroRange::D2000);
+        assert_eq!(run(init), Ok(()));
+        assert_eq!(imu.accel_range(), AccelRange::G8);
+        assert_eq!(imu.gyro_range(), GyroRange::D2000);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn init_stops_on_wrong_chip() {
+        let i2c = MockI2c::new(&[read(PWR_MGMT_1, 0x40), write(PWR_MGMT_1, 0x00), read(WHO_AM_I, 0x00)]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        let init = imu.init_with_gyro_accel_range(Duration::from_millis(100), AccelRange::G8, GyroRange::D2000);
+        assert_eq!(run(init), Err(DroneError::InvalidChipId(0x00)));
+        assert_eq!(imu.accel_range(), AccelRange::G2);
+        imu.i2c.done();
+    }
+}
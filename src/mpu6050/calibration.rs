@@ -0,0 +1,140 @@
+use crate::errors::{DroneError, I2cErrorExt, Result};
+use crate::mpu6050::{Mpu6050, ScaledSample, STANDARD_GRAVITY};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, ReadNorFlash};
+
+pub const CALIBRATION_LEN: usize = 32;
+/// Last 4 KiB sector of the 2 MiB flash, excluded from the firmware image in `memory.x`.
+pub const CALIBRATION_FLASH_OFFSET: u32 = 2048 * 1024 - 4096;
+
+const CALIBRATION_MAGIC: u32 = 0x4D50_4331;
+const SAMPLE_DELAY: Duration = Duration::from_millis(2);
+
+/// Sensor biases subtracted from every scaled sample.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    /// m/s²
+    pub accel_offset: [f32; 3],
+    /// rad/s
+    pub gyro_offset: [f32; 3]
+}
+
+impl Calibration {
+    pub fn apply(&self, sample: &mut ScaledSample) {
+        for axis in 0..3 {
+            sample.accel[axis] -= self.accel_offset[axis];
+            sample.gyro[axis] -= self.gyro_offset[axis];
+        }
+    }
+
+    /// Little-endian blob: magic, accel offsets, gyro offsets, checksum.
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_LEN] {
+        let mut bytes = [0; CALIBRATION_LEN];
+        bytes[..4].copy_from_slice(&CALIBRATION_MAGIC.to_le_bytes());
+        let offsets = self.accel_offset.iter().chain(self.gyro_offset.iter());
+        for (chunk, offset) in bytes[4..28].as_chunks_mut::<4>().0.iter_mut().zip(offsets) {
+            chunk.copy_from_slice(&offset.to_le_bytes());
+        }
+        let checksum = checksum(&bytes[..28]);
+        bytes[28..].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; CALIBRATION_LEN]) -> Result<Self> {
+        let word = |i: usize| [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
+        if u32::from_le_bytes(word(0)) != CALIBRATION_MAGIC
+            || u32::from_le_bytes(word(28)) != checksum(&bytes[..28]) {
+            return Err(DroneError::InvalidCalibration)
+        }
+        let offset = |i: usize| f32::from_le_bytes(word(4 + i * 4));
+        Ok(Calibration {
+            accel_offset: [offset(0), offset(1), offset(2)],
+            gyro_offset: [offset(3), offset(4), offset(5)]
+        })
+    }
+
+    /// Erases the sector at `offset` and writes the blob to its start.
+    pub fn store<F: NorFlash>(&self, flash: &mut F, offset: u32) -> Result<()> {
+        flash.erase(offset, offset + F::ERASE_SIZE as u32).map_err(|e| e.kind())?;
+        flash.write(offset, &self.to_bytes()).map_err(|e| e.kind())?;
+        Ok(())
+    }
+
+    pub fn load<F: ReadNorFlash>(flash: &mut F, offset: u32) -> Result<Self> {
+        let mut bytes = [0; CALIBRATION_LEN];
+        flash.read(offset, &mut bytes).map_err(|e| e.kind())?;
+        Calibration::from_bytes(&bytes)
+    }
+}
+
+/// FNV-1a, enough to reject erased or torn sectors.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+impl<I: I2c> Mpu6050<I>
+where
+    I::Error: I2cErrorExt
+{
+    pub fn calibration(&self) -> Calibration {
+        self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    /// Averages `samples` readings taken while the board sits still and level with Z up,
+    /// and installs the resulting offsets. Zero samples is rejected with [`DroneError::InvalidCalibration`].
+    pub async fn calibrate(&mut self, samples: u32) -> Result<Calibration> {
+        if samples == 0 {
+            return Err(DroneError::InvalidCalibration)
+        }
+        let mut accel = [0.0f32; 3];
+        let mut gyro = [0.0f32; 3];
+        for _ in 0..samples {
+            let sample = self.read_sample().await?.scale(self.accel_range, self.gyro_range);
+            for axis in 0..3 {
+                accel[axis] += sample.accel[axis];
+                gyro[axis] += sample.gyro[axis];
+            }
+            Timer::after(SAMPLE_DELAY).await;
+        }
+        let n = samples as f32;
+        let mut calibration = Calibration {
+            accel_offset: accel.map(|v| v / n),
+            gyro_offset: gyro.map(|v| v / n)
+        };
+        calibration.accel_offset[2] -= STANDARD_GRAVITY;
+        self.calibration = calibration;
+        Ok(calibration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, MockI2c};
+    use crate::mpu6050::Address;
+
+    #[test]
+    fn calibrate_rejects_zero_samples() {
+        let mut imu = Mpu6050::new(MockI2c::new(&[]), Address::Ad0Low);
+        assert_eq!(run(imu.calibrate(0)), Err(DroneError::InvalidCalibration));
+        assert_eq!(imu.calibration(), Calibration::default());
+    }
+
+    #[test]
+    fn blob_round_trips_and_rejects_corruption() {
+        let calibration = Calibration {
+            accel_offset: [0.1, -0.2, 0.3],
+            gyro_offset: [-0.01, 0.02, -0.03]
+        };
+        let mut bytes = calibration.to_bytes();
+        assert_eq!(Calibration::from_bytes(&bytes), Ok(calibration));
+        bytes[10] ^= 1;
+        assert_eq!(Calibration::from_bytes(&bytes), Err(DroneError::InvalidCalibration));
+        assert_eq!(Calibration::from_bytes(&[0xff; CALIBRATION_LEN]), Err(DroneError::InvalidCalibration));
+    }
+}
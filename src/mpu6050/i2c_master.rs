@@ -0,0 +1,168 @@
+use crate::errors::{DroneError, I2cErrorExt, Result};
+use crate::mpu6050::{
+    I2cMstCtrl, I2cMstStatus, I2cSlv4Ctrl, I2cSlvAddr, I2cSlvCtrl, IntPinCfg, MasterClock, Mpu6050, Sample,
+    UserCtrl, EXT_SENS_DATA_00, I2C_SLV0_ADDR, I2C_SLV0_CTRL, I2C_SLV0_DO, I2C_SLV0_REG, I2C_SLV4_ADDR,
+    I2C_SLV4_DI, I2C_SLV4_DO, I2C_SLV4_REG, ACCEL_XOUT_H, SAMPLE_LEN,
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+pub const EXT_SENS_DATA_LEN: usize = 24;
+/// Longest read a single slave channel can mirror.
+pub const SLAVE_MAX_LEN: u8 = 15;
+
+const SLV4_TIMEOUT: Duration = Duration::from_millis(10);
+const SLV4_POLL: Duration = Duration::from_micros(100);
+
+/// Slave channels polled every sample period. SLV4 is reserved for one-shot transfers.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum SlaveChannel {
+    Slv0 = 0,
+    Slv1,
+    Slv2,
+    Slv3
+}
+
+impl SlaveChannel {
+    fn addr_reg(self) -> u8 {
+        I2C_SLV0_ADDR + 3 * self as u8
+    }
+
+    fn reg_reg(self) -> u8 {
+        I2C_SLV0_REG + 3 * self as u8
+    }
+
+    fn ctrl_reg(self) -> u8 {
+        I2C_SLV0_CTRL + 3 * self as u8
+    }
+
+    fn do_reg(self) -> u8 {
+        I2C_SLV0_DO + self as u8
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum SlaveOp {
+    /// Read `len` bytes into `EXT_SENS_DATA`, after the data of lower-numbered channels.
+    Read { len: u8 },
+    /// Write one byte every sample period.
+    Write { data: u8 }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SlaveConfig {
+    /// 7-bit address on the aux bus.
+    pub address: u8,
+    pub register: u8,
+    pub op: SlaveOp,
+    /// Swap byte pairs, for sensors that report little-endian words.
+    pub byte_swap: bool
+}
+
+impl<I: I2c> Mpu6050<I>
+where
+    I::Error: I2cErrorExt
+{
+    /// Enables the internal master on the aux bus. Data ready is held until the external
+    /// sensor data of all slave channels has been loaded.
+    pub async fn enable_i2c_master(&mut self, clock: MasterClock) -> Result<()> {
+        self.set_i2c_bypass(false).await?;
+        let mst_ctrl = I2cMstCtrl::new()
+            .with_i2c_mst_clk(clock)
+            .with_wait_for_es(true);
+        self.write_register(mst_ctrl).await?;
+        self.modify_register(|r: UserCtrl| r.with_i2c_mst_en(true)).await?;
+        Ok(())
+    }
+
+    pub async fn disable_i2c_master(&mut self) -> Result<()> {
+        self.modify_register(|r: UserCtrl| r.with_i2c_mst_en(false)).await?;
+        Ok(())
+    }
+
+    /// Connects the aux bus directly to the host bus, only valid while the master is disabled.
+    pub async fn set_i2c_bypass(&mut self, enabled: bool) -> Result<()> {
+        self.int_pin_cfg = self.modify_register(|r: IntPinCfg| r.with_i2c_bypass_en(enabled)).await?;
+        Ok(())
+    }
+
+    pub async fn configure_slave(&mut self, channel: SlaveChannel, config: SlaveConfig) -> Result<()> {
+        let (read, len) = match config.op {
+            SlaveOp::Read { len } => (true, len.min(SLAVE_MAX_LEN)),
+            SlaveOp::Write { data } => {
+                self.write(channel.do_reg(), &[data]).await?;
+                (false, 1)
+            }
+        };
+        let addr = I2cSlvAddr::new()
+            .with_i2c_slv_addr(config.address & 0x7F)
+            .with_i2c_slv_rw(read);
+        let ctrl = I2cSlvCtrl::new()
+            .with_i2c_slv_len(len)
+            .with_i2c_slv_byte_sw(config.byte_swap)
+            .with_i2c_slv_en(true);
+        self.write(channel.addr_reg(), &addr.into_bytes()).await?;
+        self.write(channel.reg_reg(), &[config.register]).await?;
+        self.write(channel.ctrl_reg(), &ctrl.into_bytes()).await?;
+        Ok(())
+    }
+
+    pub async fn disable_slave(&mut self, channel: SlaveChannel) -> Result<()> {
+        self.write(channel.ctrl_reg(), &I2cSlvCtrl::new().into_bytes()).await
+    }
+
+    pub async fn slv4_read(&mut self, address: u8, register: u8) -> Result<u8> {
+        let addr = I2cSlvAddr::new()
+            .with_i2c_slv_addr(address & 0x7F)
+            .with_i2c_slv_rw(true);
+        self.slv4_transfer(addr, register).await?;
+        self.read(I2C_SLV4_DI).await
+    }
+
+    pub async fn slv4_write(&mut self, address: u8, register: u8, data: u8) -> Result<()> {
+        let addr = I2cSlvAddr::new().with_i2c_slv_addr(address & 0x7F);
+        self.write(I2C_SLV4_DO, &[data]).await?;
+        self.slv4_transfer(addr, register).await
+    }
+
+    async fn slv4_transfer(&mut self, addr: I2cSlvAddr, register: u8) -> Result<()> {
+        self.write(I2C_SLV4_ADDR, &addr.into_bytes()).await?;
+        self.write(I2C_SLV4_REG, &[register]).await?;
+        self.write_register(I2cSlv4Ctrl::new().with_i2c_slv4_en(true)).await?;
+        let deadline = Instant::now() + SLV4_TIMEOUT;
+        loop {
+            let status: I2cMstStatus = self.read_register().await?;
+            if status.i2c_lost_arb() {
+                return Err(DroneError::AuxArbitrationLost(addr.i2c_slv_addr()))
+            }
+            if status.i2c_slv4_nack() {
+                return Err(DroneError::AuxNack(addr.i2c_slv_addr()))
+            }
+            if status.i2c_slv4_done() {
+                return Ok(())
+            }
+            if Instant::now() >= deadline {
+                return Err(DroneError::AuxTimeout(addr.i2c_slv_addr()))
+            }
+            Timer::after(SLV4_POLL).await;
+        }
+    }
+
+    /// Reads the mirrored slave data, `data.len()` is clamped to [`EXT_SENS_DATA_LEN`].
+    pub async fn read_external(&mut self, data: &mut [u8]) -> Result<()> {
+        let len = data.len().min(EXT_SENS_DATA_LEN);
+        self.read_bytes(EXT_SENS_DATA_00, &mut data[..len]).await
+    }
+
+    /// Reads a sample and the first `external.len()` bytes of external sensor data in one burst,
+    /// so both come from the same sample period.
+    pub async fn read_sample_with_external(&mut self, external: &mut [u8]) -> Result<Sample> {
+        let len = external.len().min(EXT_SENS_DATA_LEN);
+        let mut data = [0; SAMPLE_LEN + EXT_SENS_DATA_LEN];
+        self.read_bytes(ACCEL_XOUT_H, &mut data[..SAMPLE_LEN + len]).await?;
+        external[..len].copy_from_slice(&data[SAMPLE_LEN..SAMPLE_LEN + len]);
+        let mut sample = [0; SAMPLE_LEN];
+        sample.copy_from_slice(&data[..SAMPLE_LEN]);
+        Ok(Sample::from_bytes(&sample))
+    }
+}
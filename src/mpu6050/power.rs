@@ -0,0 +1,192 @@
+use crate::errors::{DroneError, I2cErrorExt, Result};
+use crate::mpu6050::{LpWakeCtrl, Mpu6050, PwrMgmt1, PwrMgmt2, SignalPathReset};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+const RESET_TIMEOUT: Duration = Duration::from_millis(200);
+const RESET_POLL: Duration = Duration::from_millis(1);
+const SIGNAL_PATH_RESET_DELAY: Duration = Duration::from_millis(100);
+
+/// Axes held in standby, indexed X, Y, Z.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Standby {
+    pub accel: [bool; 3],
+    pub gyro: [bool; 3]
+}
+
+impl Standby {
+    pub const GYRO: Standby = Standby {
+        accel: [false; 3],
+        gyro: [true; 3]
+    };
+}
+
+impl<I: I2c> Mpu6050<I>
+where
+    I::Error: I2cErrorExt
+{
+    pub async fn standby(&mut self) -> Result<Standby> {
+        let r: PwrMgmt2 = self.read_register().await?;
+        Ok(Standby {
+            accel: [r.stby_xa(), r.stby_ya(), r.stby_za()],
+            gyro: [r.stby_xg(), r.stby_yg(), r.stby_zg()]
+        })
+    }
+
+    pub async fn set_standby(&mut self, standby: Standby) -> Result<()> {
+        let [xa, ya, za] = standby.accel;
+        let [xg, yg, zg] = standby.gyro;
+        self.modify_register(|r: PwrMgmt2| r
+            .with_stby_xa(xa)
+            .with_stby_ya(ya)
+            .with_stby_za(za)
+            .with_stby_xg(xg)
+            .with_stby_yg(yg)
+            .with_stby_zg(zg)
+        ).await?;
+        Ok(())
+    }
+
+    /// Accelerometer-only low-power mode: the chip sleeps and wakes at `wake` to take one
+    /// accel sample. Gyros and the temperature sensor are powered down.
+    pub async fn enter_cycle_mode(&mut self, wake: LpWakeCtrl) -> Result<()> {
+        // Entering again must not replace the saved state with the cycle-mode one.
+        let restore = match self.cycle_restore {
+            Some(restore) => restore,
+            None => (self.read_register::<PwrMgmt1>().await?.temp_dis(), self.read_register().await?)
+        };
+        self.set_standby(Standby::GYRO).await?;
+        self.modify_register(|r: PwrMgmt2| r.with_lp_wake_ctrl(wake)).await?;
+        self.modify_register(|r: PwrMgmt1| r
+            .with_sleep(false)
+            .with_cycle(true)
+            .with_temp_dis(true)
+        ).await?;
+        self.cycle_restore = Some(restore);
+        Ok(())
+    }
+
+    /// Leaves cycle mode and restores the standby axes and temperature sensor from before
+    /// [`Self::enter_cycle_mode`], or takes everything out of standby if it was never entered.
+    pub async fn exit_cycle_mode(&mut self) -> Result<()> {
+        let (temp_dis, pwr_mgmt2) = self.cycle_restore.unwrap_or((false, PwrMgmt2::new()));
+        self.modify_register(|r: PwrMgmt1| r
+            .with_cycle(false)
+            .with_temp_dis(temp_dis)
+        ).await?;
+        self.write_register(pwr_mgmt2).await?;
+        self.cycle_restore = None;
+        Ok(())
+    }
+
+    /// Resets the chip and its signal paths, then restores the cached configuration.
+    /// Used to recover an IMU that started returning garbage.
+    pub async fn reset(&mut self) -> Result<()> {
+        self.modify_register(|r: PwrMgmt1| r.with_device_reset(true)).await?;
+        let deadline = Instant::now() + RESET_TIMEOUT;
+        loop {
+            Timer::after(RESET_POLL).await;
+            // The chip may NACK while it reboots, keep polling until the deadline.
+            if let Ok(r) = self.read_register::<PwrMgmt1>().await {
+                if !r.device_reset() {
+                    break
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(DroneError::ResetTimeout)
+            }
+        }
+        let signal_path_reset = SignalPathReset::new()
+            .with_temp_reset(true)
+            .with_accel_reset(true)
+            .with_gyro_reset(true);
+        self.write_register(signal_path_reset).await?;
+        Timer::after(SIGNAL_PATH_RESET_DELAY).await;
+        self.apply_config().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{run, MockI2c, Transaction};
+    use crate::mpu6050::{
+        AccelRange, Address, GyroRange, ACCEL_CONFIG, CONFIG, GYRO_CONFIG, INT_ENABLE, INT_PIN_CFG, PWR_MGMT_1,
+        PWR_MGMT_2, SIGNAL_PATH_RESET, SMPLRT_DIV,
+    };
+    use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+
+    const ADDR: u8 = Address::Ad0Low as u8;
+
+    fn read(reg: u8, value: u8) -> Transaction {
+        Transaction::write_read(ADDR, &[reg], &[value])
+    }
+
+    fn write(reg: u8, value: u8) -> Transaction {
+        Transaction::write(ADDR, &[reg, value])
+    }
+
+    #[test]
+    fn cycle_mode_restores_previous_state() {
+        let i2c = MockI2c::new(&[
+            read(PWR_MGMT_1, 0x09),
+            read(PWR_MGMT_2, 0x01),
+            read(PWR_MGMT_2, 0x01),
+            write(PWR_MGMT_2, 0x07),
+            read(PWR_MGMT_2, 0x07),
+            write(PWR_MGMT_2, 0x47),
+            read(PWR_MGMT_1, 0x09),
+            write(PWR_MGMT_1, 0x29),
+            read(PWR_MGMT_1, 0x29),
+            write(PWR_MGMT_1, 0x09),
+            write(PWR_MGMT_2, 0x01)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        assert_eq!(run(imu.enter_cycle_mode(LpWakeCtrl::Hz5)), Ok(()));
+        assert_eq!(run(imu.exit_cycle_mode()), Ok(()));
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn reset_polls_through_nacks_and_reapplies_config() {
+        let nack = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+        let i2c = MockI2c::new(&[
+            read(PWR_MGMT_1, 0x01),
+            write(PWR_MGMT_1, 0x81),
+            // First poll NACKs on every attempt while the chip reboots.
+            read(PWR_MGMT_1, 0).with_error(nack),
+            read(PWR_MGMT_1, 0).with_error(nack),
+            read(PWR_MGMT_1, 0).with_error(nack),
+            read(PWR_MGMT_1, 0x80),
+            read(PWR_MGMT_1, 0x40),
+            write(SIGNAL_PATH_RESET, 0x07),
+            read(PWR_MGMT_1, 0x40),
+            write(PWR_MGMT_1, 0x00),
+            read(ACCEL_CONFIG, 0x00),
+            write(ACCEL_CONFIG, 0x10),
+            read(GYRO_CONFIG, 0x00),
+            write(GYRO_CONFIG, 0x18),
+            read(CONFIG, 0x00),
+            write(CONFIG, 0x00),
+            write(SMPLRT_DIV, 0x00),
+            write(INT_PIN_CFG, 0x00),
+            write(INT_ENABLE, 0x00)
+        ]);
+        let mut imu = Mpu6050::new(i2c, Address::Ad0Low);
+        imu.accel_range = AccelRange::G8;
+        imu.gyro_range = GyroRange::D2000;
+        assert_eq!(run(imu.reset()), Ok(()));
+        assert_eq!(imu.bus_stats().failures, 1);
+        imu.i2c.done();
+    }
+
+    #[test]
+    fn reset_times_out_if_bit_never_clears() {
+        let mut script = vec![read(PWR_MGMT_1, 0x00), write(PWR_MGMT_1, 0x80)];
+        let polls = (RESET_TIMEOUT.as_ticks() / RESET_POLL.as_ticks()) as usize;
+        script.extend((0..polls).map(|_| read(PWR_MGMT_1, 0x80)));
+        let mut imu = Mpu6050::new(MockI2c::new(&script), Address::Ad0Low);
+        assert_eq!(run(imu.reset()), Err(DroneError::ResetTimeout));
+        imu.i2c.done();
+    }
+}
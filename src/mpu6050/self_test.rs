@@ -0,0 +1,179 @@
+use crate::errors::{I2cErrorExt, Result};
+use crate::mpu6050::{
+    AccelRange, GyroRange, Mpu6050, SELF_TEST_A, SELF_TEST_X, SELF_TEST_Y, SELF_TEST_Z,
+};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+
+/// Allowed change from factory trim, in percent.
+pub const SELF_TEST_TOLERANCE: f32 = 14.0;
+
+const SELF_TEST_SAMPLES: u32 = 32;
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+const SAMPLE_DELAY: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct AxisSelfTest {
+    pub factory_trim: f32,
+    pub response: f32,
+    /// Change of the self-test response from factory trim, in percent.
+    pub deviation: f32,
+    pub passed: bool
+}
+
+impl AxisSelfTest {
+    fn new(factory_trim: f32, response: f32) -> Self {
+        if factory_trim == 0.0 {
+            return AxisSelfTest {
+                factory_trim,
+                response,
+                deviation: f32::INFINITY,
+                passed: false
+            }
+        }
+        let deviation = (response - factory_trim) / factory_trim * 100.0;
+        AxisSelfTest {
+            factory_trim,
+            response,
+            deviation,
+            passed: libm::fabsf(deviation) <= SELF_TEST_TOLERANCE
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub accel: [AxisSelfTest; 3],
+    pub gyro: [AxisSelfTest; 3]
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.accel.iter().chain(self.gyro.iter()).all(|axis| axis.passed)
+    }
+}
+
+/// Factory trim in LSB at ±8 g / ±250 °/s, decoded from `SELF_TEST_X..=SELF_TEST_A`.
+fn factory_trim(regs: [u8; 4]) -> ([f32; 3], [f32; 3]) {
+    let [x, y, z, a] = regs;
+    let accel_test = [
+        ((x >> 3) & 0x1C) | ((a >> 4) & 0x03),
+        ((y >> 3) & 0x1C) | ((a >> 2) & 0x03),
+        ((z >> 3) & 0x1C) | (a & 0x03)
+    ];
+    let gyro_test = [x & 0x1F, y & 0x1F, z & 0x1F];
+    let accel = accel_test.map(|test| match test {
+        0 => 0.0,
+        _ => 4096.0 * 0.34 * libm::powf(0.92 / 0.34, (test as f32 - 1.0) / 30.0)
+    });
+    let mut gyro = gyro_test.map(|test| match test {
+        0 => 0.0,
+        _ => 25.0 * 131.0 * libm::powf(1.046, test as f32 - 1.0)
+    });
+    gyro[1] = -gyro[1];
+    (accel, gyro)
+}
+
+impl<I: I2c> Mpu6050<I>
+where
+    I::Error: I2cErrorExt
+{
+    /// Runs the datasheet self-test at ±8 g / ±250 °/s and restores the previous ranges.
+    pub async fn self_test(&mut self) -> Result<SelfTestReport> {
+        let accel_range = self.accel_range;
+        let gyro_range = self.gyro_range;
+        let report = self.run_self_test().await;
+        self.disable_self_test().await?;
+        self.set_accel_range(accel_range).await?;
+        self.set_gyro_range(gyro_range).await?;
+        report
+    }
+
+    async fn run_self_test(&mut self) -> Result<SelfTestReport> {
+        let mut regs = [0; 4];
+        for (value, reg) in regs.iter_mut().zip([SELF_TEST_X, SELF_TEST_Y, SELF_TEST_Z, SELF_TEST_A]) {
+            *value = self.read(reg).await?;
+        }
+        let (accel_trim, gyro_trim) = factory_trim(regs);
+
+        self.set_accel_range(AccelRange::G8).await?;
+        self.set_gyro_range(GyroRange::D250).await?;
+        self.disable_self_test().await?;
+        Timer::after(SETTLE_DELAY).await;
+        let normal = self.average_raw(SELF_TEST_SAMPLES).await?;
+
+        self.set_accel_range_with_self_test(AccelRange::G8).await?;
+        self.set_gyro_range_with_self_test(GyroRange::D250).await?;
+        Timer::after(SETTLE_DELAY).await;
+        let test = self.average_raw(SELF_TEST_SAMPLES).await?;
+
+        let mut report = SelfTestReport::default();
+        for axis in 0..3 {
+            report.accel[axis] = AxisSelfTest::new(accel_trim[axis], test[axis] - normal[axis]);
+            report.gyro[axis] = AxisSelfTest::new(gyro_trim[axis], test[axis + 3] - normal[axis + 3]);
+        }
+        Ok(report)
+    }
+
+    /// Mean raw accel and gyro readings, in that order.
+    async fn average_raw(&mut self, samples: u32) -> Result<[f32; 6]> {
+        let mut sum = [0i32; 6];
+        for _ in 0..samples {
+            let sample = self.read_sample().await?;
+            for axis in 0..3 {
+                sum[axis] += sample.accel[axis] as i32;
+                sum[axis + 3] += sample.gyro[axis] as i32;
+            }
+            Timer::after(SAMPLE_DELAY).await;
+        }
+        Ok(sum.map(|v| v as f32 / samples as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn factory_trim_decodes_split_accel_bits() {
+        // XA = 0b101_10, YA = 0b000_01, ZA = 0b111_11; XG = 1, YG = 2, ZG = 0.
+        let (accel, gyro) = factory_trim([0b1010_0001, 0b0000_0010, 0b1110_0000, 0b0010_0111]);
+        assert_close(accel[0], 2795.4717);
+        assert_close(accel[1], 4096.0 * 0.34);
+        assert_close(accel[2], 4096.0 * 0.92);
+        assert_close(gyro[0], 25.0 * 131.0);
+        assert_close(gyro[1], -25.0 * 131.0 * 1.046);
+        assert_eq!(gyro[2], 0.0);
+    }
+
+    #[test]
+    fn zero_trim_fails() {
+        let (accel, _) = factory_trim([0; 4]);
+        assert_eq!(accel, [0.0; 3]);
+        let axis = AxisSelfTest::new(accel[0], 1500.0);
+        assert!(!axis.passed);
+        assert_eq!(axis.deviation, f32::INFINITY);
+    }
+
+    #[test]
+    fn deviation_is_checked_against_tolerance() {
+        let axis = AxisSelfTest::new(1000.0, 1100.0);
+        assert_close(axis.deviation, 10.0);
+        assert!(axis.passed);
+        assert!(!AxisSelfTest::new(1000.0, 1200.0).passed);
+        assert!(!AxisSelfTest::new(1000.0, 800.0).passed);
+    }
+
+    #[test]
+    fn negated_gyro_y_trim_passes_negative_response() {
+        let (_, gyro) = factory_trim([0, 0b0000_0001, 0, 0]);
+        let axis = AxisSelfTest::new(gyro[1], -3000.0);
+        assert_close(axis.deviation, -8.396947);
+        assert!(axis.passed);
+        assert!(!AxisSelfTest::new(gyro[1], 3000.0).passed);
+    }
+}
@@ -0,0 +1,510 @@
+use embassy_rp::uart::{Config, DataBits, Parity, StopBits};
+use embassy_time::{Duration, Instant};
+
+pub const RC_CHANNELS: usize = 16;
+
+/// One decoded receiver frame.
+#[derive(Debug, Copy, Clone, PartialEq, defmt::Format)]
+pub struct RcFrame {
+    /// Sticks and switches in `[-1, 1]`, centre at zero; entries past `count` are zero.
+    pub channels: [f32; RC_CHANNELS],
+    pub count: u8,
+    /// The receiver lost the transmitter and is sending its failsafe positions.
+    pub failsafe: bool,
+    /// The receiver missed the frame preceding this one.
+    pub frame_lost: bool
+}
+
+impl RcFrame {
+    fn new(count: usize) -> Self {
+        RcFrame {
+            channels: [0.0; RC_CHANNELS],
+            count: count.min(RC_CHANNELS) as u8,
+            failsafe: false,
+            frame_lost: false
+        }
+    }
+
+    pub fn channel(&self, index: usize) -> f32 {
+        self.channels.get(index).copied().unwrap_or(0.0)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, defmt::Format)]
+pub struct LinkStats {
+    pub frames: u32,
+    /// Frames dropped for a bad checksum, footer or length.
+    pub errors: u32,
+    /// Frames the receiver reported as lost.
+    pub lost_frames: u32,
+    pub failsafes: u32,
+    /// Uplink quality in percent, when the protocol reports it.
+    pub link_quality: Option<u8>,
+    pub rssi_dbm: Option<i16>,
+    pub snr_db: Option<i8>
+}
+
+/// Receivers that deliver frames over a UART byte stream.
+pub trait RcParser {
+    /// Feeds one received byte, returning a frame once it completes one.
+    fn push(&mut self, byte: u8) -> Option<RcFrame>;
+
+    fn stats(&self) -> LinkStats;
+
+    fn reset(&mut self);
+}
+
+/// Centre and half-range of the 11 bit channel values used by SBUS and CRSF.
+const PACKED_CENTER: f32 = 992.0;
+const PACKED_SPAN: f32 = 819.0;
+
+/// Unpacks 16 little-endian 11 bit channels.
+fn unpack_channels(data: &[u8], frame: &mut RcFrame) {
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    let mut channel = 0;
+    for &byte in data {
+        accumulator |= (byte as u32) << bits;
+        bits += 8;
+        while bits >= 11 && channel < RC_CHANNELS {
+            let raw = (accumulator & 0x07ff) as f32;
+            frame.channels[channel] = ((raw - PACKED_CENTER) / PACKED_SPAN).clamp(-1.0, 1.0);
+            accumulator >>= 11;
+            bits -= 11;
+            channel += 1;
+        }
+    }
+}
+
+pub const SBUS_FRAME_LEN: usize = 25;
+const SBUS_HEADER: u8 = 0x0f;
+const SBUS_FLAG_FRAME_LOST: u8 = 1 << 2;
+const SBUS_FLAG_FAILSAFE: u8 = 1 << 3;
+
+/// Inverted 100 kbaud 8E2, the RP2040 UART undoes the inversion itself.
+pub fn sbus_uart_config() -> Config {
+    let mut config = Config::default();
+    config.baudrate = 100_000;
+    config.data_bits = DataBits::DataBits8;
+    config.parity = Parity::ParityEven;
+    config.stop_bits = StopBits::STOP2;
+    config.invert_rx = true;
+    config
+}
+
+/// Futaba SBUS: a 0x0F header, 22 bytes of packed channels, a flags byte and a footer.
+#[derive(Debug, Copy, Clone)]
+pub struct Sbus {
+    buffer: [u8; SBUS_FRAME_LEN],
+    len: usize,
+    stats: LinkStats
+}
+
+impl Sbus {
+    pub fn new() -> Self {
+        Sbus {
+            buffer: [0; SBUS_FRAME_LEN],
+            len: 0,
+            stats: LinkStats::default()
+        }
+    }
+
+    fn decode(&mut self) -> RcFrame {
+        let mut frame = RcFrame::new(RC_CHANNELS);
+        unpack_channels(&self.buffer[1..23], &mut frame);
+        let flags = self.buffer[23];
+        frame.frame_lost = flags & SBUS_FLAG_FRAME_LOST != 0;
+        frame.failsafe = flags & SBUS_FLAG_FAILSAFE != 0;
+        self.stats.frames += 1;
+        self.stats.lost_frames += frame.frame_lost as u32;
+        self.stats.failsafes += frame.failsafe as u32;
+        frame
+    }
+}
+
+impl Default for Sbus {
+    fn default() -> Self {
+        Sbus::new()
+    }
+}
+
+impl RcParser for Sbus {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        if self.len == 0 && byte != SBUS_HEADER {
+            return None
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        if self.len < SBUS_FRAME_LEN {
+            return None
+        }
+
+        // SBUS2 receivers cycle the upper footer nibble for telemetry slots.
+        let footer = self.buffer[SBUS_FRAME_LEN - 1];
+        if footer == 0x00 || footer & 0x0f == 0x04 {
+            self.len = 0;
+            return Some(self.decode())
+        }
+        self.stats.errors += 1;
+        // Resynchronise on the next header byte already received, if any.
+        match self.buffer[1..].iter().position(|&b| b == SBUS_HEADER) {
+            Some(start) => {
+                self.buffer.copy_within(start + 1.., 0);
+                self.len = SBUS_FRAME_LEN - 1 - start;
+            }
+            None => self.len = 0
+        }
+        None
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    fn reset(&mut self) {
+        *self = Sbus::new();
+    }
+}
+
+const CRSF_MAX_FRAME_LEN: usize = 64;
+const CRSF_ADDRESS_FLIGHT_CONTROLLER: u8 = 0xc8;
+const CRSF_ADDRESS_TRANSMITTER: u8 = 0xee;
+const CRSF_ADDRESS_RECEIVER: u8 = 0xec;
+const CRSF_SYNC: u8 = 0xea;
+const CRSF_TYPE_LINK_STATISTICS: u8 = 0x14;
+const CRSF_TYPE_RC_CHANNELS: u8 = 0x16;
+
+/// 420 kbaud 8N1, the ExpressLRS default.
+pub fn crsf_uart_config() -> Config {
+    let mut config = Config::default();
+    config.baudrate = 420_000;
+    config
+}
+
+/// CRC-8 with polynomial 0xD5 over the type and payload bytes.
+pub fn crsf_crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { crc << 1 ^ 0xd5 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CrsfState {
+    Address,
+    Length,
+    Body { len: usize }
+}
+
+/// Crossfire/ExpressLRS: address, length, type, payload and CRC-8.
+/// CRSF has no failsafe flag; the receiver simply stops sending channels.
+#[derive(Debug, Copy, Clone)]
+pub struct Crsf {
+    state: CrsfState,
+    buffer: [u8; CRSF_MAX_FRAME_LEN],
+    received: usize,
+    stats: LinkStats
+}
+
+impl Crsf {
+    pub fn new() -> Self {
+        Crsf {
+            state: CrsfState::Address,
+            buffer: [0; CRSF_MAX_FRAME_LEN],
+            received: 0,
+            stats: LinkStats::default()
+        }
+    }
+
+    fn decode(&mut self, body: usize) -> Option<RcFrame> {
+        let (payload, crc) = self.buffer[..body].split_at(body - 1);
+        if crsf_crc8(payload) != crc[0] {
+            self.stats.errors += 1;
+            return None
+        }
+        let (kind, payload) = (payload[0], &payload[1..]);
+        match kind {
+            CRSF_TYPE_RC_CHANNELS if payload.len() == 22 => {
+                let mut frame = RcFrame::new(RC_CHANNELS);
+                unpack_channels(payload, &mut frame);
+                self.stats.frames += 1;
+                Some(frame)
+            }
+            CRSF_TYPE_LINK_STATISTICS if payload.len() == 10 => {
+                let active_antenna = payload[4];
+                let rssi = if active_antenna == 0 { payload[0] } else { payload[1] };
+                self.stats.rssi_dbm = Some(-(rssi as i16));
+                self.stats.link_quality = Some(payload[2]);
+                self.stats.snr_db = Some(payload[3] as i8);
+                None
+            }
+            _ => None
+        }
+    }
+}
+
+impl Default for Crsf {
+    fn default() -> Self {
+        Crsf::new()
+    }
+}
+
+impl RcParser for Crsf {
+    fn push(&mut self, byte: u8) -> Option<RcFrame> {
+        match self.state {
+            CrsfState::Address => {
+                if matches!(
+                    byte,
+                    CRSF_ADDRESS_FLIGHT_CONTROLLER | CRSF_ADDRESS_TRANSMITTER | CRSF_ADDRESS_RECEIVER | CRSF_SYNC
+                ) {
+                    self.state = CrsfState::Length;
+                }
+                None
+            }
+            CrsfState::Length => {
+                // Length covers type, payload and CRC.
+                self.state = if (2..=CRSF_MAX_FRAME_LEN - 2).contains(&(byte as usize)) {
+                    self.received = 0;
+                    CrsfState::Body { len: byte as usize }
+                } else {
+                    self.stats.errors += 1;
+                    CrsfState::Address
+                };
+                None
+            }
+            CrsfState::Body { len } => {
+                self.buffer[self.received] = byte;
+                self.received += 1;
+                if self.received < len {
+                    return None
+                }
+                self.state = CrsfState::Address;
+                self.decode(len)
+            }
+        }
+    }
+
+    fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    fn reset(&mut self) {
+        *self = Crsf::new();
+    }
+}
+
+/// Pulses longer than this end a PPM frame.
+const PPM_SYNC_GAP: Duration = Duration::from_micros(2_700);
+const PPM_MIN_PULSE: Duration = Duration::from_micros(750);
+const PPM_MAX_PULSE: Duration = Duration::from_micros(2_250);
+const PPM_MIN_CHANNELS: usize = 4;
+
+/// PPM sum signal, decoded from the time between consecutive edges of one polarity.
+#[derive(Debug, Copy, Clone)]
+pub struct Ppm {
+    last_edge: Option<Instant>,
+    frame: RcFrame,
+    channel: usize,
+    synced: bool,
+    stats: LinkStats
+}
+
+impl Ppm {
+    pub fn new() -> Self {
+        Ppm {
+            last_edge: None,
+            frame: RcFrame::new(0),
+            channel: 0,
+            synced: false,
+            stats: LinkStats::default()
+        }
+    }
+
+    /// Feeds the time of one edge, returning a frame at each sync gap that ends a valid frame.
+    pub fn edge(&mut self, at: Instant) -> Option<RcFrame> {
+        let last = self.last_edge.replace(at)?;
+        let width = at.checked_duration_since(last)?;
+
+        if width >= PPM_SYNC_GAP {
+            let complete = self.synced && self.channel >= PPM_MIN_CHANNELS;
+            let mut frame = self.frame;
+            frame.count = self.channel as u8;
+            self.synced = true;
+            self.channel = 0;
+            self.frame = RcFrame::new(0);
+            if complete {
+                self.stats.frames += 1;
+                return Some(frame)
+            }
+            return None
+        }
+        if !self.synced {
+            return None
+        }
+        if !(PPM_MIN_PULSE..=PPM_MAX_PULSE).contains(&width) || self.channel >= RC_CHANNELS {
+            self.stats.errors += 1;
+            self.synced = false;
+            return None
+        }
+        let us = width.as_micros() as f32;
+        self.frame.channels[self.channel] = ((us - 1500.0) / 500.0).clamp(-1.0, 1.0);
+        self.channel += 1;
+        None
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    pub fn reset(&mut self) {
+        *self = Ppm::new();
+    }
+}
+
+impl Default for Ppm {
+    fn default() -> Self {
+        Ppm::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SBUS frame with channels at 172, 992, 1811, 1500, 172, 1811 and the rest centred.
+    const SBUS_FRAME: [u8; SBUS_FRAME_LEN] = [
+        0x0f, 0xac, 0x00, 0xdf, 0xc4, 0xb9, 0xcb, 0x8a, 0x89, 0x83, 0x0f, 0x7c, 0xe0,
+        0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x00, 0x00
+    ];
+
+    /// CRSF RC channels frame carrying the same channels as [`SBUS_FRAME`].
+    const CRSF_CHANNELS: [u8; 26] = [
+        0xc8, 0x18, 0x16, 0xac, 0x00, 0xdf, 0xc4, 0xb9, 0xcb, 0x8a, 0x89, 0x83, 0x0f,
+        0x7c, 0xe0, 0x03, 0x1f, 0xf8, 0xc0, 0x07, 0x3e, 0xf0, 0x81, 0x0f, 0x7c, 0x8a
+    ];
+
+    /// CRSF link statistics: -60/-75 dBm, 100 % LQ, 9 dB SNR on antenna 0.
+    const CRSF_LINK_STATS: [u8; 14] = [
+        0xc8, 0x0c, 0x14, 0x3c, 0x4b, 0x64, 0x09, 0x00, 0x03, 0x02, 0x32, 0x62, 0x0a, 0x65
+    ];
+
+    fn feed(parser: &mut impl RcParser, bytes: &[u8]) -> Vec<RcFrame> {
+        bytes.iter().filter_map(|&b| parser.push(b)).collect()
+    }
+
+    fn assert_captured_channels(frame: &RcFrame) {
+        assert_eq!(frame.count, 16);
+        assert_eq!(&frame.channels[..3], &[-1.0, 0.0, 1.0]);
+        assert!((frame.channel(3) - 508.0 / 819.0).abs() < 1e-6);
+        assert_eq!(frame.channel(4), -1.0);
+        assert_eq!(frame.channel(5), 1.0);
+        assert!(frame.channels[6..].iter().all(|&c| c == 0.0));
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        assert_eq!(crsf_crc8(b"123456789"), 0xbc);
+    }
+
+    #[test]
+    fn sbus_decodes_captured_frame() {
+        let mut sbus = Sbus::new();
+        let frames = feed(&mut sbus, &SBUS_FRAME);
+        assert_eq!(frames.len(), 1);
+        assert_captured_channels(&frames[0]);
+        assert!(!frames[0].failsafe && !frames[0].frame_lost);
+    }
+
+    #[test]
+    fn sbus_reports_flags() {
+        let mut bytes = SBUS_FRAME;
+        bytes[23] = SBUS_FLAG_FAILSAFE | SBUS_FLAG_FRAME_LOST;
+        let mut sbus = Sbus::new();
+        let frames = feed(&mut sbus, &bytes);
+        assert!(frames[0].failsafe && frames[0].frame_lost);
+        assert_eq!(sbus.stats().failsafes, 1);
+        assert_eq!(sbus.stats().lost_frames, 1);
+    }
+
+    #[test]
+    fn sbus_resyncs_after_noise() {
+        let mut stream = vec![0x55, 0x0f, 0x12];
+        stream.extend_from_slice(&SBUS_FRAME);
+        let mut sbus = Sbus::new();
+        let frames = feed(&mut sbus, &stream);
+        assert_eq!(frames.len(), 1);
+        assert_captured_channels(&frames[0]);
+        assert_eq!(sbus.stats().errors, 1);
+    }
+
+    #[test]
+    fn sbus_accepts_sbus2_footer() {
+        let mut bytes = SBUS_FRAME;
+        bytes[24] = 0x14;
+        assert_eq!(feed(&mut Sbus::new(), &bytes).len(), 1);
+    }
+
+    #[test]
+    fn crsf_decodes_captured_frames() {
+        let mut crsf = Crsf::new();
+        let mut stream = CRSF_LINK_STATS.to_vec();
+        stream.extend_from_slice(&CRSF_CHANNELS);
+        let frames = feed(&mut crsf, &stream);
+        assert_eq!(frames.len(), 1);
+        assert_captured_channels(&frames[0]);
+        let stats = crsf.stats();
+        assert_eq!(stats.frames, 1);
+        assert_eq!(stats.rssi_dbm, Some(-60));
+        assert_eq!(stats.link_quality, Some(100));
+        assert_eq!(stats.snr_db, Some(9));
+    }
+
+    #[test]
+    fn crsf_drops_bad_crc() {
+        let mut bytes = CRSF_CHANNELS;
+        bytes[10] ^= 0x01;
+        let mut crsf = Crsf::new();
+        assert!(feed(&mut crsf, &bytes).is_empty());
+        assert_eq!(crsf.stats().errors, 1);
+        assert_eq!(feed(&mut crsf, &CRSF_CHANNELS).len(), 1);
+    }
+
+    #[test]
+    fn ppm_decodes_pulse_train() {
+        let pulses = [1000, 1500, 2000, 1250, 1500, 1500, 1750, 1100];
+        let mut ppm = Ppm::new();
+        let mut at = Instant::from_micros(0);
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            for width in pulses.iter().copied().chain([12_000]) {
+                frames.extend(ppm.edge(at));
+                at += Duration::from_micros(width);
+            }
+        }
+        frames.extend(ppm.edge(at));
+        // The first gap only synchronises; every later one completes a frame.
+        assert_eq!(frames.len(), 2);
+        let frame = frames[1];
+        assert_eq!(frame.count, 8);
+        assert_eq!(&frame.channels[..4], &[-1.0, 0.0, 1.0, -0.5]);
+        assert_eq!(ppm.stats().frames, 2);
+    }
+
+    #[test]
+    fn ppm_drops_frame_with_glitch() {
+        let mut ppm = Ppm::new();
+        let mut at = Instant::from_micros(0);
+        let mut frames = Vec::new();
+        for width in [3_000, 1500, 1500, 300, 1500, 1500, 3_000, 1500, 1500, 1500, 1500, 3_000] {
+            frames.extend(ppm.edge(at));
+            at += Duration::from_micros(width);
+        }
+        frames.extend(ppm.edge(at));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].count, 4);
+        assert_eq!(ppm.stats().errors, 1);
+    }
+}
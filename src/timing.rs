@@ -0,0 +1,64 @@
+use embassy_time::{Duration, Instant};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub struct LoopStats {
+    pub iterations: u32,
+    /// Iterations that took longer than one period.
+    pub overruns: u32,
+    /// Largest deviation between consecutive starts and the nominal period.
+    pub max_jitter: Duration,
+    /// Worst-case execution time of one iteration.
+    pub wcet: Duration,
+    pub last_execution: Duration
+}
+
+/// Measures a fixed-rate loop; call `start` at the top of every iteration and `finish` at the end.
+#[derive(Debug, Copy, Clone)]
+pub struct LoopTimer {
+    period: Duration,
+    started: Option<Instant>,
+    stats: LoopStats
+}
+
+impl LoopTimer {
+    pub fn new(period: Duration) -> Self {
+        LoopTimer {
+            period,
+            started: None,
+            stats: LoopStats::default()
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn start(&mut self, now: Instant) {
+        if let Some(last) = self.started.replace(now) {
+            let interval = now.saturating_duration_since(last);
+            let jitter = if interval > self.period { interval - self.period } else { self.period - interval };
+            self.stats.max_jitter = self.stats.max_jitter.max(jitter);
+        }
+    }
+
+    pub fn finish(&mut self, now: Instant) {
+        let Some(started) = self.started else {
+            return
+        };
+        let execution = now.saturating_duration_since(started);
+        self.stats.iterations = self.stats.iterations.wrapping_add(1);
+        self.stats.last_execution = execution;
+        self.stats.wcet = self.stats.wcet.max(execution);
+        if execution > self.period {
+            self.stats.overruns += 1;
+        }
+    }
+
+    pub fn stats(&self) -> LoopStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = LoopStats::default();
+    }
+}